@@ -0,0 +1,219 @@
+use crate::api::ApiError;
+use crate::policy::Policy;
+use crate::queue::{IdQuery, QueueError, QueueState, StudentRequest, Ta};
+use crate::server::{QueueRef, Settings};
+use actix_web::dev::Payload;
+use actix_web::{error, web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::future::{ready, Ready};
+
+/// Proof that a request carried the staff token as `Authorization: Bearer <token>`.
+/// The admin API is disabled when no token is configured.
+pub struct Staff;
+
+impl FromRequest for Staff {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Staff, actix_web::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let expected = req
+            .app_data::<web::Data<Settings>>()
+            .and_then(|settings| settings.staff_token.clone());
+        let Some(expected) = expected else {
+            return ready(Err(error::ErrorForbidden("The admin API is disabled.")));
+        };
+
+        let provided = req
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if provided == Some(expected.as_str()) {
+            ready(Ok(Staff))
+        } else {
+            ready(Err(error::ErrorUnauthorized("Missing or invalid staff token.")))
+        }
+    }
+}
+
+/// Registers the `/api/admin` routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/admin")
+            .route("/queue", web::get().to(list))
+            .route("/claim", web::post().to(claim))
+            .route("/resolve", web::post().to(resolve))
+            .route("/merge", web::post().to(merge))
+            .route("/resolve-group", web::post().to(resolve_group))
+            .route("/requeue", web::post().to(requeue))
+            .route("/remove", web::post().to(remove))
+            .route("/reorder", web::post().to(reorder))
+            .route("/open", web::post().to(open))
+            .route("/soft-close", web::post().to(soft_close))
+            .route("/close", web::post().to(close))
+            .route("/new-session", web::post().to(new_session)),
+    );
+}
+
+/// The queue as staff see it, without the students' secrets.
+#[derive(Serialize)]
+struct QueueView<'a> {
+    state: QueueState,
+    policy: Policy,
+    /// Students being helped, followed by everyone waiting in policy order.
+    students: Vec<StudentRequest>,
+    tas: &'a [Ta],
+}
+
+#[derive(Deserialize)]
+struct ClaimQuery {
+    id: String,
+    ta: String,
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    ids: Vec<String>,
+    ta: String,
+    location: String,
+}
+
+#[derive(Deserialize)]
+struct TaQuery {
+    ta: String,
+}
+
+#[derive(Deserialize)]
+struct ReorderQuery {
+    id: String,
+    position: usize,
+}
+
+fn respond(result: Result<(), QueueError>, action: &str, id: &str) -> HttpResponse {
+    match result {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            let message = format!("Could not {} request {}: {}", action, id, e);
+            HttpResponse::build(ApiError::from(e).status_code()).body(message)
+        }
+    }
+}
+
+/// Responds to a change to the whole queue, e.g. opening it.
+fn changed(result: Result<(), QueueError>, action: &str) -> HttpResponse {
+    match result {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            let message = format!("Could not {}: {}", action, e);
+            HttpResponse::build(ApiError::from(e).status_code()).body(message)
+        }
+    }
+}
+
+async fn list(_: Staff, data: QueueRef) -> HttpResponse {
+    let queue = data.lock().unwrap();
+    HttpResponse::Ok().json(QueueView {
+        state: queue.state,
+        policy: queue.policy,
+        students: queue.ordered().into_iter().map(StudentRequest::without_secrets).collect(),
+        tas: &queue.tas,
+    })
+}
+
+async fn claim(
+    _: Staff,
+    data: QueueRef,
+    query: web::Query<ClaimQuery>,
+) -> HttpResponse {
+    let mut queue = data.lock().unwrap();
+    queue.add_ta(Ta::new(query.ta.clone()));
+    info!("{} claimed {} via admin API", query.ta, query.id);
+    respond(queue.claim(query.id.clone(), &query.ta), "claim", &query.id)
+}
+
+async fn resolve(
+    _: Staff,
+    data: QueueRef,
+    query: web::Query<IdQuery>,
+) -> HttpResponse {
+    let result = data.lock().unwrap().resolve(query.id.clone());
+    respond(result, "resolve", &query.id)
+}
+
+/// Claims every request in the JSON body for one group session.
+async fn merge(
+    _: Staff,
+    data: QueueRef,
+    body: web::Json<MergeRequest>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let mut queue = data.lock().unwrap();
+    queue.add_ta(Ta::new(body.ta.clone()));
+    info!("{} merged {} requests at {} via admin API", body.ta, body.ids.len(), body.location);
+    let ids = body.ids.join(", ");
+    respond(queue.merge(body.ids, &body.ta, body.location), "merge", &ids)
+}
+
+async fn resolve_group(
+    _: Staff,
+    data: QueueRef,
+    query: web::Query<TaQuery>,
+) -> HttpResponse {
+    match data.lock().unwrap().resolve_group(&query.ta) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => HttpResponse::build(ApiError::from(e).status_code())
+            .body(format!("{} is not helping anyone.", query.ta)),
+    }
+}
+
+async fn requeue(
+    _: Staff,
+    data: QueueRef,
+    query: web::Query<IdQuery>,
+) -> HttpResponse {
+    let result = data.lock().unwrap().requeue(query.id.clone());
+    respond(result, "requeue", &query.id)
+}
+
+async fn remove(
+    _: Staff,
+    data: QueueRef,
+    query: web::Query<IdQuery>,
+) -> HttpResponse {
+    let result = data.lock().unwrap().remove(query.id.clone());
+    respond(result, "remove", &query.id)
+}
+
+async fn reorder(
+    _: Staff,
+    data: QueueRef,
+    query: web::Query<ReorderQuery>,
+) -> HttpResponse {
+    let result = data
+        .lock()
+        .unwrap()
+        .reorder(query.id.clone(), query.position);
+    respond(result, "reorder", &query.id)
+}
+
+async fn open(_: Staff, data: QueueRef) -> HttpResponse {
+    let result = data.lock().unwrap().set_state(QueueState::Open);
+    changed(result, "open the queue")
+}
+
+async fn soft_close(_: Staff, data: QueueRef) -> HttpResponse {
+    let result = data.lock().unwrap().set_state(QueueState::SoftClosed);
+    changed(result, "soft-close the queue")
+}
+
+async fn close(_: Staff, data: QueueRef) -> HttpResponse {
+    let result = data.lock().unwrap().set_state(QueueState::Closed);
+    changed(result, "close the queue")
+}
+
+/// Starts a new session, resetting statistics and per-session visit limits.
+async fn new_session(_: Staff, data: QueueRef) -> HttpResponse {
+    let result = data.lock().unwrap().start_session();
+    changed(result, "start a new session")
+}
@@ -0,0 +1,229 @@
+use crate::queue::{JoinError, Queue, QueueError, StudentInfo, StudentRequest};
+use crate::rules::Rejection;
+use crate::server::{self, QueueRef, Settings};
+use crate::stats;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Local};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+/// Registers the `/api/v1` routes. Every response is JSON, and failures are
+/// `{"error": "..."}` with a status code that says what went wrong.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/v1")
+            .app_data(web::JsonConfig::default().error_handler(|e, _| ApiError::Invalid(e.to_string()).into()))
+            .app_data(web::PathConfig::default().error_handler(|e, _| ApiError::Invalid(e.to_string()).into()))
+            .app_data(web::QueryConfig::default().error_handler(|e, _| ApiError::Invalid(e.to_string()).into()))
+            .route("/requests", web::post().to(join))
+            .route("/requests/{id}", web::get().to(ticket))
+            .route("/requests/{id}", web::delete().to(leave))
+            .route("/requests/{id}/events", web::get().to(events))
+            .route("/attach", web::post().to(attach))
+            .route("/stats", web::get().to(server::get_stats))
+            .route("/hours", web::get().to(server::get_hours))
+            .route("/labs", web::get().to(server::get_labs))
+            .route("/rooms", web::get().to(server::get_rooms)),
+    );
+}
+
+/// Why an API call failed.
+#[derive(Debug)]
+pub enum ApiError {
+    Queue(QueueError),
+    Join(JoinError),
+    /// The body is incomplete or names an unknown lab, room or seat.
+    Invalid(String),
+    /// The CSID is not on the course roster.
+    NotEnrolled,
+    /// Turned away by the visit rules.
+    Rejected(Rejection),
+    /// Office hours are over.
+    Closed,
+}
+
+impl From<QueueError> for ApiError {
+    fn from(e: QueueError) -> ApiError {
+        ApiError::Queue(e)
+    }
+}
+
+impl From<JoinError> for ApiError {
+    fn from(e: JoinError) -> ApiError {
+        ApiError::Join(e)
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Queue(e) => write!(f, "{}", e),
+            ApiError::Join(JoinError::Closed) => {
+                write!(f, "The office hour queue is closed to new students right now.")
+            }
+            ApiError::Join(JoinError::Duplicate { .. }) => write!(
+                f,
+                "You are already in the office hour queue. Only one request per CSID is allowed."
+            ),
+            ApiError::Join(JoinError::UnknownInvite) => write!(
+                f,
+                "That invite is not for anyone in the queue. Ask your partner for a new link."
+            ),
+            ApiError::Join(JoinError::Io(e)) => write!(f, "The queue could not be saved: {}", e),
+            ApiError::Invalid(message) => write!(f, "{}", message),
+            ApiError::NotEnrolled => write!(
+                f,
+                "That CS ID is not on the course roster. Please check it and try again."
+            ),
+            ApiError::Rejected(rejection) => write!(f, "{}", rejection.reason),
+            ApiError::Closed => write!(f, "Office hours are over."),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    /// When a student turned away by the visit rules may rejoin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_at: Option<DateTime<Local>>,
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Queue(QueueError::NotFound) => StatusCode::NOT_FOUND,
+            ApiError::Queue(QueueError::Conflict) => StatusCode::CONFLICT,
+            ApiError::Queue(QueueError::Invalid) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Join(JoinError::Closed) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Join(JoinError::Duplicate { .. }) => StatusCode::CONFLICT,
+            ApiError::Join(JoinError::UnknownInvite) => StatusCode::NOT_FOUND,
+            ApiError::Queue(QueueError::Io(_)) | ApiError::Join(JoinError::Io(_)) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ApiError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::NotEnrolled => StatusCode::FORBIDDEN,
+            ApiError::Rejected(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Closed => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.to_string(),
+            retry_at: match self {
+                ApiError::Rejected(rejection) => rejection.retry_at,
+                _ => None,
+            },
+        })
+    }
+}
+
+/// A student's own view of their request.
+#[derive(Serialize, Debug)]
+pub struct Ticket {
+    pub id: String,
+    /// Everyone on the request, e.g. `Alice & Bob`.
+    pub names: String,
+    /// Shared with partners so they can attach to this request.
+    pub invite: String,
+    /// Place among waiting students, starting at 1; 0 while being helped.
+    pub position: usize,
+    pub estimated_wait_minutes: i64,
+    /// The TA helping the student, once claimed.
+    pub ta: Option<String>,
+    pub location: Option<String>,
+    pub meeting_link: Option<String>,
+}
+
+impl Ticket {
+    fn of(queue: &Queue, request: &StudentRequest) -> Result<Ticket, QueueError> {
+        let position = queue.position(request.id.clone())?;
+        Ok(Ticket {
+            id: request.id.clone(),
+            names: request.names(),
+            invite: request.invite.clone(),
+            position,
+            estimated_wait_minutes: stats::estimated_wait(queue, position).num_minutes(),
+            ta: request.helper().map(str::to_string),
+            location: request.location.clone(),
+            meeting_link: request.meeting_link.clone(),
+        })
+    }
+}
+
+/// The `{id}` in a request's URL. Extracted by name, since routes under
+/// `/q/{name}` also carry the queue name.
+#[derive(Deserialize)]
+struct RequestPath {
+    id: String,
+}
+
+#[derive(Deserialize)]
+pub struct AttachRequest {
+    pub invite: String,
+    pub name: String,
+    pub csid: String,
+}
+
+/// Joins the queue, setting the cookie that authorizes the other calls.
+async fn join(
+    data: QueueRef,
+    settings: web::Data<Settings>,
+    body: web::Json<StudentInfo>,
+) -> Result<HttpResponse, ApiError> {
+    let mut queue = data.lock().unwrap();
+    let request = server::admit(&queue, &settings, &body)?;
+    info!("Student request received: {:?}", request);
+    queue.add(request.clone())?;
+    Ok(HttpResponse::Created()
+        .cookie(server::secret_cookie(&request.id, &request.secret))
+        .json(Ticket::of(&queue, &request)?))
+}
+
+/// Adds a partner to the request with the given invite code.
+async fn attach(
+    data: QueueRef,
+    settings: web::Data<Settings>,
+    body: web::Json<AttachRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let mut queue = data.lock().unwrap();
+    let member = server::admit_member(&queue, &settings, &body)?;
+    let secret = member.secret.clone();
+    let id = queue.attach(&body.invite, member)?;
+    let request = queue.find(&id).expect("Attached request is in the queue");
+    Ok(HttpResponse::Created()
+        .cookie(server::secret_cookie(&id, &secret))
+        .json(Ticket::of(&queue, request)?))
+}
+
+async fn ticket(
+    req: HttpRequest,
+    data: QueueRef,
+    path: web::Path<RequestPath>,
+) -> Result<HttpResponse, ApiError> {
+    let id = &path.id;
+    let queue = data.lock().unwrap();
+    if server::still_waiting_after_close(&queue, id) {
+        return Err(ApiError::Closed);
+    }
+    queue.authorize(id, server::request_secret(&req, id).as_deref())?;
+    let request = queue.find(id).expect("Authorized requests are in the queue");
+    Ok(HttpResponse::Ok().json(Ticket::of(&queue, request)?))
+}
+
+async fn leave(
+    req: HttpRequest,
+    data: QueueRef,
+    path: web::Path<RequestPath>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner().id;
+    let mut queue = data.lock().unwrap();
+    queue.leave(id.clone(), server::request_secret(&req, &id).as_deref())?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn events(req: HttpRequest, data: QueueRef, path: web::Path<RequestPath>) -> HttpResponse {
+    server::updates(&req, &data, path.into_inner().id)
+}
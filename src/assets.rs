@@ -0,0 +1,41 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Pages and the stylesheet, compiled in so the binary runs from anywhere.
+const EMBEDDED: &[(&str, &str)] = &[
+    ("attach.html", include_str!("public/attach.html")),
+    ("closed.html", include_str!("public/closed.html")),
+    ("done.html", include_str!("public/done.html")),
+    ("index.html", include_str!("public/index.html")),
+    ("rejected.html", include_str!("public/rejected.html")),
+    ("styles.css", include_str!("public/styles.css")),
+    ("waiting.html", include_str!("public/waiting.html")),
+];
+
+/// The asset called `name`, taken from the override directory when it has
+/// one and from the binary otherwise. Only plain file names are looked up.
+pub fn get(overrides: Option<&Path>, name: &str) -> Option<Cow<'static, [u8]>> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return None;
+    }
+    if let Some(data) = overrides.and_then(|dir| std::fs::read(dir.join(name)).ok()) {
+        return Some(Cow::Owned(data));
+    }
+    EMBEDDED
+        .iter()
+        .find(|(embedded, _)| *embedded == name)
+        .map(|(_, data)| Cow::Borrowed(data.as_bytes()))
+}
+
+pub fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
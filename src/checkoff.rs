@@ -0,0 +1,120 @@
+use crate::journal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A lab that can be checked off, e.g.
+///
+/// ```toml
+/// [[lab]]
+/// name = "lab3"
+/// items = ["Compiles without warnings", "Passes the tests", "Explains the design"]
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Lab {
+    pub name: String,
+    pub items: Vec<String>,
+}
+
+/// Rubrics for every lab that can be checked off.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Rubric {
+    #[serde(rename = "lab", default)]
+    pub labs: Vec<Lab>,
+}
+
+impl Rubric {
+    pub fn load(path: &Path) -> Result<Rubric, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read rubric {}: {}", path.display(), e))?;
+        let rubric: Rubric = toml::from_str(&data)
+            .map_err(|e| format!("Invalid rubric {}: {}", path.display(), e))?;
+        for lab in &rubric.labs {
+            if lab.items.is_empty() {
+                return Err(format!("Lab {} has no rubric items", lab.name));
+            }
+        }
+        Ok(rubric)
+    }
+
+    pub fn lab(&self, name: &str) -> Option<&Lab> {
+        self.labs.iter().find(|lab| lab.name == name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ItemResult {
+    pub item: String,
+    pub passed: bool,
+}
+
+/// One graded checkoff, as appended to the gradebook.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Grade {
+    pub csid: String,
+    pub name: String,
+    pub lab: String,
+    pub ta: String,
+    pub checked_at: DateTime<Utc>,
+    pub items: Vec<ItemResult>,
+}
+
+impl Grade {
+    pub fn passed(&self) -> bool {
+        self.items.iter().all(|x| x.passed)
+    }
+}
+
+/// The gradebook lives next to the queue snapshot, e.g. `queue.json` -> `queue.gradebook`.
+pub fn path_for(snapshot: &Path) -> PathBuf {
+    snapshot.with_extension("gradebook")
+}
+
+/// Appends `grade` to the gradebook at `path`, one JSON object per line.
+pub fn append(path: &Path, grade: &Grade) -> io::Result<()> {
+    journal::append_archive(path, grade)
+}
+
+/// Loads every grade in the gradebook, skipping lines torn by a crash.
+pub fn load(path: &Path) -> io::Result<Vec<Grade>> {
+    journal::read_archive(path)
+}
+
+/// A gradebook row as exported for the LMS.
+#[derive(Serialize)]
+struct Row<'a> {
+    csid: &'a str,
+    name: &'a str,
+    lab: &'a str,
+    ta: &'a str,
+    checked_at: DateTime<Utc>,
+    passed_items: usize,
+    total_items: usize,
+    result: &'a str,
+    /// Per-item results, e.g. `Compiles: pass; Passes the tests: fail`.
+    details: String,
+}
+
+pub fn export_csv<'a>(grades: impl Iterator<Item = &'a Grade>, out: impl Write) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    for grade in grades {
+        let details: Vec<_> = grade
+            .items
+            .iter()
+            .map(|x| format!("{}: {}", x.item, if x.passed { "pass" } else { "fail" }))
+            .collect();
+        writer.serialize(Row {
+            csid: &grade.csid,
+            name: &grade.name,
+            lab: &grade.lab,
+            ta: &grade.ta,
+            checked_at: grade.checked_at,
+            passed_items: grade.items.iter().filter(|x| x.passed).count(),
+            total_items: grade.items.len(),
+            result: if grade.passed() { "pass" } else { "fail" },
+            details: details.join("; "),
+        })?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,183 @@
+use serde::{Deserialize, Deserializer};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Ports to try binding to, in order, given as `3000` or `3000-3050`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ports {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl FromStr for Ports {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .map_err(|_| format!("'{}' is not a port number", port.trim()))
+        };
+        let ports = match s.split_once('-') {
+            Some((first, last)) => Ports { first: parse(first)?, last: parse(last)? },
+            None => {
+                let port = parse(s)?;
+                Ports { first: port, last: port }
+            }
+        };
+        if ports.first == 0 || ports.first > ports.last {
+            return Err(format!("'{}' is not a valid port range", s));
+        }
+        Ok(ports)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ports {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Port(u16),
+            Range(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Port(port) => port.to_string().parse(),
+            Raw::Range(range) => range.parse(),
+        }
+        .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Where to write instructions for reaching the server over SSH, given as a
+/// path, or `false` to not write them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum HintFile {
+    /// `~/Public/join-office-hours.txt`, where students on the same machine
+    /// can read it.
+    #[default]
+    Public,
+    Path(PathBuf),
+    Disabled,
+}
+
+impl<'de> Deserialize<'de> for HintFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Enabled(bool),
+            Path(PathBuf),
+        }
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Enabled(true) => HintFile::Public,
+            Raw::Enabled(false) => HintFile::Disabled,
+            Raw::Path(path) => HintFile::Path(path),
+        })
+    }
+}
+
+/// Where the server listens.
+#[derive(Clone, Debug)]
+pub struct Listen {
+    pub address: String,
+    pub ports: Ports,
+    /// Where to say which port was bound, if anywhere.
+    pub hint_file: Option<PathBuf>,
+}
+
+/// Deployment settings read from a TOML file, e.g.
+///
+/// ```toml
+/// address = "127.0.0.1"
+/// port = "3000-3050"
+/// static_dir = "/etc/oh-queue/theme"
+/// queue = "/var/lib/oh-queue/queue.json"
+/// log_file = "/var/log/oh-queue.log"
+/// socket = "/run/oh-queue/control.sock"
+/// hint_file = false
+/// ```
+///
+/// Every field is optional, and command-line options take precedence.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub address: String,
+    pub port: Ports,
+    /// Directory of files that replace or add to the built-in pages and
+    /// stylesheet, for theming.
+    pub static_dir: Option<PathBuf>,
+    /// Snapshot file of the default queue.
+    pub queue: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    /// Control socket for `oh-queue ctl`; defaults to one beside the queue.
+    pub socket: Option<PathBuf>,
+    pub hint_file: HintFile,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            address: "0.0.0.0".to_string(),
+            port: Ports { first: 3000, last: 3050 },
+            static_dir: None,
+            queue: None,
+            log_file: None,
+            socket: None,
+            hint_file: HintFile::Public,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config {}: {}", path.display(), e))?;
+        toml::from_str(&data).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Checks the settings that would otherwise only fail once the server
+    /// starts or a page is requested.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.address.parse::<IpAddr>().is_err() {
+            return Err(format!("'{}' is not an IP address to bind to", self.address));
+        }
+        if let Some(dir) = self.static_dir.as_ref().filter(|dir| !dir.is_dir()) {
+            return Err(format!("Static directory {} does not exist", dir.display()));
+        }
+        if let Some(log_file) = &self.log_file {
+            let parent = log_file.parent().filter(|x| !x.as_os_str().is_empty());
+            if parent.is_some_and(|x| !x.is_dir()) {
+                return Err(format!("Log directory for {} does not exist", log_file.display()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn listen(&self) -> Listen {
+        Listen {
+            address: self.address.clone(),
+            ports: self.port,
+            hint_file: match &self.hint_file {
+                HintFile::Public => dirs::home_dir().map(|home| home.join("Public").join("join-office-hours.txt")),
+                HintFile::Path(path) => Some(path.clone()),
+                HintFile::Disabled => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hint_file_is_a_path_or_a_switch() {
+        let config: Config = toml::from_str("hint_file = false").unwrap();
+        assert_eq!(config.listen().hint_file, None);
+        let config: Config = toml::from_str("hint_file = \"/srv/oh/hint.txt\"").unwrap();
+        assert_eq!(config.listen().hint_file, Some(PathBuf::from("/srv/oh/hint.txt")));
+        let config: Config = toml::from_str("hint_file = true").unwrap();
+        assert_eq!(config.hint_file, HintFile::Public);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::checkoff::{self, Grade, ItemResult};
+use crate::policy::Policy;
+use crate::queue::{Queue, QueueError, QueueState, Queues, RequestKind, StudentRequest, Ta};
+use crate::stats::Stats;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+/// The control socket lives next to the default queue's snapshot, e.g.
+/// `queue.json` -> `queue.sock`.
+pub fn path_for(snapshot: &Path) -> PathBuf {
+    snapshot.with_extension("sock")
+}
+
+/// An operation on a running instance, sent as one JSON object per line.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "cmd", rename_all = "kebab-case")]
+pub enum Command {
+    List,
+    /// Claims the next waiting student under the active policy.
+    Next { ta: String },
+    Remove { id: String },
+    Open,
+    Close,
+    Stats,
+    /// Names of every queue, the default one first.
+    Queues,
+    /// Everything the TUI shows about one queue.
+    View,
+    /// Puts a TA on duty, e.g. when their TUI attaches.
+    AddTa { ta: Ta },
+    /// Takes a TA off duty again when their TUI detaches.
+    OffDuty { ta: String },
+    Claim { id: String, ta: String },
+    Merge { ids: Vec<String>, ta: String, location: String },
+    /// Resolves everyone `ta` is helping.
+    ResolveGroup { ta: String },
+    /// Puts everyone `ta` is helping back in line.
+    RequeueGroup { ta: String },
+    /// Records a checkoff for everyone on the request and resolves it.
+    Grade { id: String, ta: String, lab: String, items: Vec<ItemResult> },
+    SetState { state: QueueState },
+    SetPolicy { policy: Policy },
+    StartSession,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Request {
+    /// Named queue to act on instead of the default one.
+    #[serde(default)]
+    pub queue: Option<String>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Reply {
+    Ok(serde_json::Value),
+    Error(String),
+}
+
+/// A request as shown by `oh-queue ctl list`, without its secrets.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Listing {
+    pub id: String,
+    pub names: String,
+    pub csid: String,
+    pub topic: String,
+    pub helper: Option<String>,
+    pub joined_at: DateTime<Utc>,
+}
+
+impl Listing {
+    fn of(request: &StudentRequest) -> Listing {
+        Listing {
+            id: request.id.clone(),
+            names: request.names(),
+            csid: request.info.csid.clone(),
+            topic: match request.info.kind {
+                RequestKind::Help => format!("{:?}", request.info.purpose),
+                RequestKind::Checkoff => format!("Checkoff {}", request.info.lab),
+            },
+            helper: request.helper().map(str::to_string),
+            joined_at: request.joined_at,
+        }
+    }
+}
+
+/// A snapshot of one queue, for drawing the TUI. Students' secrets are left
+/// out, since anyone with access to the control socket can read it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct View {
+    pub state: QueueState,
+    pub policy: Policy,
+    /// Students being helped, followed by everyone waiting in policy order.
+    pub students: Vec<StudentRequest>,
+    pub tas: Vec<Ta>,
+    pub stats: Stats,
+}
+
+impl View {
+    fn of(queue: &Queue) -> View {
+        View {
+            state: queue.state,
+            policy: queue.policy,
+            students: queue.ordered().into_iter().map(StudentRequest::without_secrets).collect(),
+            tas: queue.tas.clone(),
+            stats: Stats::compute(queue, Utc::now()),
+        }
+    }
+
+    /// Everyone `ta` is helping.
+    pub fn group(&self, ta: &str) -> Vec<&StudentRequest> {
+        self.students.iter().filter(|x| x.helper() == Some(ta)).collect()
+    }
+}
+
+/// Listens on `path`. A socket left behind by an instance that exited is
+/// replaced, but one that still answers is an error.
+pub async fn bind(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Another instance is already listening on {}", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // Access is limited to the owner and the socket's group
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    info!("Control socket listening on {}", path.display());
+    Ok(listener)
+}
+
+/// Accepts control connections until the listener fails.
+pub async fn serve(listener: UnixListener, queues: Queues) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let queues = queues.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, queues).await {
+                warn!("Control connection failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: UnixStream, queues: Queues) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => handle(&queues, request),
+            Err(e) => Reply::Error(format!("Invalid request: {}", e)),
+        };
+        let mut data = serde_json::to_string(&reply)?;
+        data.push('\n');
+        writer.write_all(data.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Applies `request` to the queues and describes the outcome.
+pub fn handle(queues: &Queues, request: Request) -> Reply {
+    if let Command::Queues = request.command {
+        return ok(queues.iter().map(|(name, _)| name).collect::<Vec<_>>());
+    }
+    let queue_ref = match &request.queue {
+        Some(name) => match queues.get(name) {
+            Some(queue_ref) => queue_ref,
+            None => return Reply::Error(format!("There is no queue named {}.", name)),
+        },
+        None => queues.first(),
+    };
+    let mut queue = queue_ref.lock().unwrap();
+    if !matches!(request.command, Command::View) {
+        info!("Control request: {:?}", request.command);
+    }
+    match request.command {
+        Command::List => ok(queue.ordered().into_iter().map(Listing::of).collect::<Vec<_>>()),
+        Command::Next { ta } => next(&mut queue, ta),
+        Command::Remove { id } => done(
+            queue.remove(id.clone()),
+            format!("There is no request {} in the queue.", id),
+        ),
+        Command::Open => done(queue.set_state(QueueState::Open), String::new()),
+        Command::Close => done(queue.set_state(QueueState::Closed), String::new()),
+        Command::Stats => ok(Stats::compute(&queue, Utc::now())),
+        Command::Queues => unreachable!("Handled before looking up a queue"),
+        Command::View => ok(View::of(&queue)),
+        Command::AddTa { ta } => {
+            queue.go_on_duty(ta);
+            ok(())
+        }
+        Command::OffDuty { ta } => {
+            queue.go_off_duty(&ta);
+            ok(())
+        }
+        Command::Claim { id, ta } => done(
+            queue.claim(id, &ta),
+            format!("{} could not claim that student.", ta),
+        ),
+        Command::Merge { ids, ta, location } => done(
+            queue.merge(ids, &ta, location),
+            format!("{} could not start a group session.", ta),
+        ),
+        Command::ResolveGroup { ta } => {
+            done(queue.resolve_group(&ta), format!("{} is not helping anyone.", ta))
+        }
+        Command::RequeueGroup { ta } => {
+            let ids: Vec<String> = queue.group(&ta).iter().map(|x| x.id.clone()).collect();
+            let requeued = ids.into_iter().try_for_each(|id| queue.requeue(id));
+            done(requeued, format!("{} could not put their group back in line.", ta))
+        }
+        Command::Grade { id, ta, lab, items } => grade(&mut queue, id, ta, lab, items),
+        Command::SetState { state } => done(queue.set_state(state), String::new()),
+        Command::SetPolicy { policy } => done(queue.set_policy(policy), String::new()),
+        Command::StartSession => done(queue.start_session(), String::new()),
+    }
+}
+
+/// Replies with `message` when the change does not apply to the queue, or
+/// with the reason it could not be saved.
+fn done(result: Result<(), QueueError>, message: String) -> Reply {
+    match result {
+        Ok(_) => ok(()),
+        Err(e @ QueueError::Io(_)) => Reply::Error(e.to_string()),
+        Err(_) => Reply::Error(message),
+    }
+}
+
+/// Appends the same grade for the student and each partner to the
+/// gradebook, then resolves the request. The request stays claimed if the
+/// gradebook cannot be written.
+fn grade(queue: &mut Queue, id: String, ta: String, lab: String, items: Vec<ItemResult>) -> Reply {
+    let Some(student) = queue.find(&id).filter(|x| !x.is_waiting()) else {
+        return Reply::Error("Only a checkoff being helped can be graded.".to_string());
+    };
+    let mut graded = vec![(student.info.csid.clone(), student.info.name.clone())];
+    graded.extend(student.members.iter().map(|x| (x.csid.clone(), x.name.clone())));
+    for (csid, name) in graded {
+        let grade = Grade {
+            csid,
+            name,
+            lab: lab.clone(),
+            ta: ta.clone(),
+            checked_at: Utc::now(),
+            items: items.clone(),
+        };
+        if let Err(e) = checkoff::append(&checkoff::path_for(queue.path()), &grade) {
+            return Reply::Error(format!("Could not write the gradebook: {}", e));
+        }
+    }
+    match queue.resolve(id) {
+        Ok(_) => ok(()),
+        Err(e) => Reply::Error(e.to_string()),
+    }
+}
+
+fn next(queue: &mut Queue, ta: String) -> Reply {
+    let Some(id) = queue.ordered_waiting().first().map(|x| x.id.clone()) else {
+        return Reply::Error("Nobody is waiting.".to_string());
+    };
+    queue.add_ta(Ta::new(ta.clone()));
+    match queue.claim(id.clone(), &ta) {
+        Ok(_) => ok(Listing::of(queue.find(&id).expect("Claimed request is in the queue"))),
+        Err(e @ QueueError::Io(_)) => Reply::Error(e.to_string()),
+        Err(_) => Reply::Error(format!("{} is already helping someone.", ta)),
+    }
+}
+
+fn ok(value: impl Serialize) -> Reply {
+    Reply::Ok(serde_json::to_value(value).expect("Replies serialize to JSON"))
+}
+
+/// Sends one request to the instance listening on `path` and waits for its
+/// reply.
+pub fn send(path: &Path, request: &Request) -> io::Result<Reply> {
+    let mut stream = std::os::unix::net::UnixStream::connect(path).map_err(|e| {
+        io::Error::new(e.kind(), format!("Could not connect to {}: {}", path.display(), e))
+    })?;
+    let mut data = serde_json::to_string(request)?;
+    data.push('\n');
+    stream.write_all(data.as_bytes())?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
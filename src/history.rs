@@ -0,0 +1,152 @@
+use crate::journal;
+use crate::queue::{Purpose, StudentRequest};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How a request left the queue.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Resolved,
+    /// The student left the queue themselves.
+    Left,
+    /// Staff removed the request without helping.
+    Removed,
+    /// Office hours ended while the student was still waiting.
+    Unserved,
+}
+
+/// A finished office-hours visit, kept after the request leaves the queue.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Visit {
+    pub id: String,
+    pub name: String,
+    pub csid: String,
+    pub purpose: Purpose,
+    pub details: String,
+    pub steps: String,
+    pub ta: Option<String>,
+    pub joined_at: DateTime<Utc>,
+    pub claimed_at: Option<DateTime<Utc>>,
+    pub finished_at: DateTime<Utc>,
+    pub outcome: Outcome,
+}
+
+impl Visit {
+    pub fn new(request: StudentRequest, outcome: Outcome, at: DateTime<Utc>) -> Visit {
+        Visit {
+            ta: request.helper().map(str::to_string),
+            id: request.id,
+            name: request.info.name,
+            csid: request.info.csid,
+            purpose: request.info.purpose,
+            details: request.info.details,
+            steps: request.info.steps,
+            joined_at: request.joined_at,
+            claimed_at: request.claimed_at,
+            finished_at: at,
+            outcome,
+        }
+    }
+
+    /// One visit for the student who joined and one for each partner.
+    pub fn all(request: StudentRequest, outcome: Outcome, at: DateTime<Utc>) -> Vec<Visit> {
+        let members = request.members.clone();
+        let visit = Visit::new(request, outcome, at);
+        let mut visits: Vec<Visit> = members
+            .into_iter()
+            .map(|member| Visit {
+                name: member.name,
+                csid: member.csid,
+                ..visit.clone()
+            })
+            .collect();
+        visits.insert(0, visit);
+        visits
+    }
+}
+
+/// Append-only archive of every visit, stored as one JSON object per line.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    pub visits: Vec<Visit>,
+}
+
+/// The archive lives next to the queue snapshot, e.g. `queue.json` -> `queue.history`.
+pub fn path_for(snapshot: &Path) -> PathBuf {
+    snapshot.with_extension("history")
+}
+
+impl History {
+    /// Loads every visit in the archive, skipping lines torn by a crash.
+    pub fn load(path: PathBuf) -> io::Result<History> {
+        let visits = journal::read_archive(&path)?;
+        Ok(History { path, visits })
+    }
+
+    /// Whether `visit` is already archived, i.e. the same member's visit for
+    /// the same request.
+    pub fn contains(&self, visit: &Visit) -> bool {
+        self.visits.iter().any(|x| x.id == visit.id && x.csid == visit.csid)
+    }
+
+    pub fn append(&mut self, visit: Visit) -> io::Result<()> {
+        journal::append_archive(&self.path, &visit)?;
+        self.visits.push(visit);
+        Ok(())
+    }
+
+    /// Visits that started between `from` and `to`, inclusive, in local time.
+    pub fn between(
+        &self,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> impl Iterator<Item = &Visit> {
+        self.visits.iter().filter(move |visit| {
+            let day = visit.joined_at.with_timezone(&Local).date_naive();
+            from.is_none_or(|from| day >= from) && to.is_none_or(|to| day <= to)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format '{}', expected csv or json", s)),
+        }
+    }
+}
+
+pub fn export<'a>(
+    visits: impl Iterator<Item = &'a Visit>,
+    format: Format,
+    out: impl Write,
+) -> io::Result<()> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for visit in visits {
+                writer.serialize(visit)?;
+            }
+            writer.flush()
+        }
+        Format::Json => {
+            let visits: Vec<_> = visits.collect();
+            serde_json::to_writer_pretty(out, &visits)?;
+            Ok(())
+        }
+    }
+}
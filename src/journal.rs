@@ -0,0 +1,212 @@
+use crate::policy::Policy;
+use crate::queue::{Member, QueueState, StudentRequest, Ta};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// A single change to the queue. Every mutation goes through one of these so
+/// the queue can be rebuilt by replaying the journal.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Event {
+    Join(Box<StudentRequest>),
+    Attach { id: String, member: Member },
+    Leave { id: String },
+    /// A partner left, leaving the rest of the request in line.
+    Detach { id: String, csid: String },
+    Remove { id: String },
+    Claim { id: String, ta: String },
+    Resolve { id: String },
+    Requeue { id: String },
+    Merge { ids: Vec<String>, ta: String, location: String },
+    /// Moves a waiting request to the given 1-based position among waiting requests.
+    Move { id: String, position: usize },
+    SetState(QueueState),
+    SetPolicy(Policy),
+    StartSession,
+    AddTa(Ta),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Entry {
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    pub event: Event,
+}
+
+/// Append-only log of events written since the last snapshot.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    /// Number of entries appended since the journal was last truncated.
+    pub len: usize,
+}
+
+/// The journal lives next to the snapshot, e.g. `queue.json` -> `queue.journal`.
+pub fn path_for(snapshot: &Path) -> PathBuf {
+    snapshot.with_extension("journal")
+}
+
+impl Journal {
+    pub fn open(path: PathBuf) -> io::Result<Journal> {
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        Ok(Journal { file, len: 0 })
+    }
+
+    /// Reads every complete entry in the journal. A crash during an append can
+    /// leave a torn final line, so reading stops at the first entry that does
+    /// not parse.
+    pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    warn!("Ignoring the rest of {} after bad entry: {}", path.display(), e);
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Writes `entry` and waits for it to reach the disk. A failed write is
+    /// cut back off so later entries do not follow a torn line.
+    pub fn append(&mut self, entry: &Entry) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let end = self.file.metadata()?.len();
+        let written = self
+            .file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.file.sync_data());
+        if let Err(e) = written {
+            let _ = self.file.set_len(end);
+            return Err(e);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Drops all entries. Only call this once they are covered by a snapshot.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// Reads an archive of one JSON object per line, such as the visit history.
+/// Unlike the journal, later lines do not depend on earlier ones, so a line
+/// torn by a crash during an append is skipped rather than ending the read.
+pub fn read_archive<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut values = vec![];
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        match serde_json::from_str(&line?) {
+            Ok(value) => values.push(value),
+            Err(e) => warn!("Ignoring bad line {} of {}: {}", number + 1, path.display(), e),
+        }
+    }
+    Ok(values)
+}
+
+/// Appends `value` to an archive read by [`read_archive`] and waits for it to
+/// reach the disk. A line torn by an earlier crash is ended first, so the new
+/// one still parses.
+pub fn append_archive(path: &Path, value: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+    if file.metadata()?.len() > 0 {
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            line.insert(0, '\n');
+        }
+    }
+    file.write_all(line.as_bytes())?;
+    file.sync_data()
+}
+
+/// Replaces `path` with `data` by writing a temporary file and renaming it over
+/// the original, so readers never see a partially written file. The rename is
+/// on disk before this returns, so callers may then discard what it replaces.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    let dir = path.parent().filter(|x| !x.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::testing::temp_dir;
+
+    fn entry(seq: u64) -> Entry {
+        Entry {
+            seq,
+            at: Utc::now(),
+            event: Event::StartSession,
+        }
+    }
+
+    #[test]
+    fn read_stops_at_a_torn_final_line() {
+        let path = temp_dir("journal-torn").join("queue.journal");
+        let mut journal = Journal::open(path.clone()).unwrap();
+        journal.append(&entry(1)).unwrap();
+        journal.append(&entry(2)).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"seq":3,"at":"#).unwrap();
+
+        let seqs: Vec<u64> = Journal::read(&path).unwrap().iter().map(|x| x.seq).collect();
+        assert_eq!(seqs, [1, 2]);
+    }
+
+    #[test]
+    fn truncate_drops_every_entry() {
+        let path = temp_dir("journal-truncate").join("queue.journal");
+        let mut journal = Journal::open(path.clone()).unwrap();
+        journal.append(&entry(1)).unwrap();
+        journal.truncate().unwrap();
+        assert_eq!(journal.len, 0);
+        assert!(Journal::read(&path).unwrap().is_empty());
+
+        journal.append(&entry(2)).unwrap();
+        assert_eq!(Journal::read(&path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn archive_skips_a_torn_line_and_appends_after_it() {
+        let path = temp_dir("archive-torn").join("queue.history");
+        append_archive(&path, &entry(1)).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"seq":2,"at":"#).unwrap();
+        append_archive(&path, &entry(3)).unwrap();
+
+        let seqs: Vec<u64> = read_archive::<Entry>(&path).unwrap().iter().map(|x| x.seq).collect();
+        assert_eq!(seqs, [1, 3]);
+    }
+}
@@ -1,34 +1,516 @@
+mod admin;
+mod api;
+mod assets;
+mod checkoff;
+mod config;
+mod control;
+mod history;
+mod journal;
+mod policy;
 mod queue;
+mod rooms;
+mod roster;
+mod rules;
+mod schedule;
 mod server;
+mod stats;
 
 use server::*;
-use std::sync::{Arc, Mutex};
 use structopt::StructOpt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use log::info;
+use chrono::NaiveDate;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 #[derive(StructOpt, Debug)]
 #[structopt(name = "queue")]
 pub struct Opt {
     /// Path to the queue file
     #[structopt(parse(from_os_str))]
     pub path: Option<PathBuf>,
+
+    /// Run without the TUI, serving immediately until SIGTERM
+    #[structopt(long)]
+    pub headless: bool,
+
+    /// Run only the TUI, attached to the instance listening on the control socket
+    #[structopt(long, conflicts_with = "headless")]
+    pub attach: bool,
+
+    /// TOML config file for the bind address, ports and paths
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Address to bind to, overriding the config file
+    #[structopt(long)]
+    pub address: Option<String>,
+
+    /// Port or port range such as 3000-3050 to try, overriding the config file
+    #[structopt(long)]
+    pub port: Option<Ports>,
+
+    /// Directory of pages and styles that replace the built-in ones, overriding the config file
+    #[structopt(long, parse(from_os_str))]
+    pub static_dir: Option<PathBuf>,
+
+    /// Control socket path, overriding the config file
+    #[structopt(long, parse(from_os_str))]
+    pub socket: Option<PathBuf>,
+
+    /// File to write logs to, overriding the config file
+    #[structopt(long, parse(from_os_str))]
+    pub log_file: Option<PathBuf>,
+
+    /// Extra named queues as name or name=path, served under /q/<name>/
+    #[structopt(long = "queue", number_of_values = 1)]
+    pub queues: Vec<QueueSpec>,
+
+    /// TAs on duty as name or name=meeting-link; the first one is the TUI's current TA
+    #[structopt(long = "ta", number_of_values = 1)]
+    pub tas: Vec<Ta>,
+
+    /// Token TAs must send to use the admin API; disabled when unset
+    #[structopt(long, env = "OH_QUEUE_STAFF_TOKEN", hide_env_values = true)]
+    pub staff_token: Option<String>,
+
+    /// What to do when a CSID already in line joins again: reject or redirect
+    #[structopt(long, default_value = "redirect")]
+    pub on_duplicate: DuplicatePolicy,
+
+    /// TOML file of weekly office-hour windows that open and close the queue
+    #[structopt(long, parse(from_os_str))]
+    pub schedule: Option<PathBuf>,
+
+    /// Order to serve students in: fifo, first-visit, fewest-visits or round-robin
+    #[structopt(long)]
+    pub policy: Option<Policy>,
+
+    /// Most times one student can be helped per session
+    #[structopt(long)]
+    pub max_visits: Option<usize>,
+
+    /// Minutes a student must wait after being helped before rejoining
+    #[structopt(long)]
+    pub cooldown_minutes: Option<i64>,
+
+    /// Most minutes of help one student can get per day
+    #[structopt(long)]
+    pub max_help_minutes: Option<i64>,
+
+    /// CSV roster with csid,name,section columns; only listed students may join
+    #[structopt(long, parse(from_os_str))]
+    pub roster: Option<PathBuf>,
+
+    /// Let students missing from the roster join anyway
+    #[structopt(long)]
+    pub allow_guests: bool,
+
+    /// TOML file of labs and their checkoff rubric items
+    #[structopt(long, parse(from_os_str))]
+    pub rubric: Option<PathBuf>,
+
+    /// TOML file of lab room layouts for the seating map
+    #[structopt(long, parse(from_os_str))]
+    pub rooms: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Export visit history, e.g. for end-of-semester reporting
+    Export {
+        /// First day to include (YYYY-MM-DD)
+        #[structopt(long)]
+        from: Option<NaiveDate>,
+        /// Last day to include (YYYY-MM-DD)
+        #[structopt(long)]
+        to: Option<NaiveDate>,
+        /// Output format: csv or json
+        #[structopt(long, default_value = "csv")]
+        format: history::Format,
+        /// File to write to instead of stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Named queue to export instead of the default one
+        #[structopt(long)]
+        queue: Option<String>,
+    },
+    /// Export lab checkoff grades as CSV for the LMS
+    Gradebook {
+        /// Only include this lab
+        #[structopt(long)]
+        lab: Option<String>,
+        /// File to write to instead of stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Named queue to export instead of the default one
+        #[structopt(long)]
+        queue: Option<String>,
+    },
+    /// Operate a running instance through its control socket
+    Ctl {
+        /// Named queue to act on instead of the default one
+        #[structopt(long)]
+        queue: Option<String>,
+        #[structopt(subcommand)]
+        action: CtlAction,
+    },
 }
 
-use queue::Queue;
+#[derive(StructOpt, Debug)]
+pub enum CtlAction {
+    /// List everyone in line, in the order they will be served
+    List,
+    /// Claim the next student in line
+    Next {
+        /// TA claiming the student; defaults to $USER
+        #[structopt(long)]
+        ta: Option<String>,
+    },
+    /// Remove a request from the queue
+    Remove { id: String },
+    /// Open the queue to new students
+    Open,
+    /// Close the queue
+    Close,
+    /// Show statistics for the current session
+    Stats,
+    /// Start a new session, resetting statistics and per-session visit limits
+    NewSession,
+}
+
+/// A named queue given on the command line as `name` or `name=path`.
+#[derive(Debug, Clone)]
+pub struct QueueSpec {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl std::str::FromStr for QueueSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, path) = match s.split_once('=') {
+            Some((name, path)) => (name, PathBuf::from(path)),
+            None => (s, Queue::default_path_for(s)),
+        };
+        let valid = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(format!("queue name '{}' may only use letters, digits, - and _", name));
+        }
+        Ok(QueueSpec { name: name.to_string(), path })
+    }
+}
+
+impl Opt {
+    /// Every queue to serve. The positional path is the default queue unless
+    /// only named queues were given.
+    pub fn queue_specs(&self) -> Vec<QueueSpec> {
+        let mut specs = vec![];
+        if self.path.is_some() || self.queues.is_empty() {
+            specs.push(QueueSpec {
+                name: "default".to_string(),
+                path: self.path.clone().unwrap_or_else(Queue::default_path),
+            });
+        }
+        specs.extend(self.queues.iter().cloned());
+        specs
+    }
+}
+
+use checkoff::{ItemResult, Rubric};
+use config::{Config, Ports};
+use history::History;
+use simplelog::{LevelFilter, SimpleLogger, WriteLogger};
+use rooms::{Room, Rooms};
+use control::View;
+use queue::{Queue, QueueState, Queues, RequestKind, StudentRequest, Ta};
+use policy::Policy;
+use roster::Roster;
+use rules::Rules;
+use schedule::Schedule;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     let (tx, rx) = mpsc::channel::<ServerControlMessage>(100);
 
-    let opt = Opt::from_args();
-    let queue = Queue::init(opt.path).expect("Failed to initialize queue");
-    let queue_ref = Arc::new(Mutex::new(queue));
+    let mut opt = Opt::from_args();
+    let mut config = match &opt.config {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => Config::default(),
+    };
+    config.address = opt.address.take().unwrap_or(config.address);
+    config.port = opt.port.unwrap_or(config.port);
+    config.static_dir = opt.static_dir.take().or(config.static_dir);
+    config.log_file = opt.log_file.take().or(config.log_file);
+    config.socket = opt.socket.take().or(config.socket);
+    if opt.path.is_none() {
+        opt.path = config.queue.clone();
+    }
+
+    let specs = opt.queue_specs();
+    let socket = config.socket.clone().unwrap_or_else(|| control::path_for(&specs[0].path));
+    match opt.cmd {
+        Some(Command::Export { from, to, format, output, queue }) => {
+            let spec = find_spec(&specs, queue);
+            let history = History::load(history::path_for(&spec.path))?;
+            let visits = history.between(from, to);
+            return match output {
+                Some(path) => history::export(visits, format, std::fs::File::create(path)?),
+                None => history::export(visits, format, std::io::stdout().lock()),
+            };
+        }
+        Some(Command::Gradebook { lab, output, queue }) => {
+            let spec = find_spec(&specs, queue);
+            let grades = checkoff::load(&checkoff::path_for(&spec.path))?;
+            let grades = grades.iter().filter(|grade| lab.as_ref().is_none_or(|lab| grade.lab == *lab));
+            return match output {
+                Some(path) => checkoff::export_csv(grades, std::fs::File::create(path)?),
+                None => checkoff::export_csv(grades, std::io::stdout().lock()),
+            };
+        }
+        Some(Command::Ctl { queue, action }) => {
+            return run_ctl(&socket, queue, action);
+        }
+        None => {}
+    }
 
-    tokio::spawn(http_server(queue_ref.clone(), rx));
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    // The TUI owns the terminal, so it only logs to a file
+    if let Some(path) = &config.log_file {
+        let file = std::fs::OpenOptions::new().append(true).create(true).open(path)?;
+        WriteLogger::init(LevelFilter::Info, simplelog::Config::default(), file)
+            .expect("Logger is only initialized once");
+    } else if opt.headless {
+        SimpleLogger::init(LevelFilter::Info, simplelog::Config::default())
+            .expect("Logger is only initialized once");
+    }
+
+    let mut on_duty = opt.tas;
+    if on_duty.is_empty() {
+        on_duty.push(Ta::new(std::env::var("USER").unwrap_or_else(|_| "TA".to_string())));
+    }
+    let tas: Vec<String> = on_duty.iter().map(|ta| ta.name.clone()).collect();
+    let rooms = match opt.rooms {
+        Some(path) => match Rooms::load(&path) {
+            Ok(rooms) => Some(rooms),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let rubric = match opt.rubric {
+        Some(path) => match Rubric::load(&path) {
+            Ok(rubric) => Some(rubric),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    if opt.attach {
+        return run_attached(socket, on_duty, tas, rubric, rooms).await;
+    }
+    let mut queues = vec![];
+    for spec in specs {
+        let mut queue = Queue::load(spec.path).expect("Failed to initialize queue");
+        for ta in &on_duty {
+            queue.go_on_duty(ta.clone());
+        }
+        if let Some(policy) = opt.policy {
+            queue.set_policy(policy).expect("Failed to set queue policy");
+        }
+        queues.push((spec.name, queue));
+    }
+    let queues = Queues::new(queues);
 
-    // Make sure that `run_app` is an async function that returns a `Result` and takes `Send` parameters.
-    run_app(tx, queue_ref).await
+    let schedule = match opt.schedule {
+        Some(path) => match Schedule::load(&path) {
+            Ok(schedule) => Some(schedule),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let roster = match opt.roster {
+        Some(path) => match Roster::load(&path) {
+            Ok(roster) => Some(roster),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    if let Some(schedule) = &schedule {
+        for (_, queue_ref) in queues.iter() {
+            tokio::spawn(schedule::run(schedule.clone(), queue_ref.clone()));
+        }
+    }
+
+    let settings = Settings {
+        staff_token: opt.staff_token,
+        duplicate_policy: opt.on_duplicate,
+        schedule,
+        rules: Rules {
+            max_visits_per_session: opt.max_visits,
+            cooldown_minutes: opt.cooldown_minutes,
+            max_help_minutes_per_day: opt.max_help_minutes,
+        },
+        roster,
+        allow_guests: opt.allow_guests,
+        rubric: rubric.clone(),
+        rooms: rooms.clone(),
+        static_dir: config.static_dir.clone(),
+    };
+    let listener = control::bind(&socket).await.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    tokio::spawn(control::serve(listener, queues.clone()));
+
+    let server = tokio::spawn(http_server(queues.clone(), settings, config.listen(), rx));
+
+    let result = if opt.headless {
+        run_headless(tx, server, queues).await
+    } else {
+        let names = queues.iter().map(|(name, _)| name.to_string()).collect();
+        // Make sure that `run_app` is an async function that returns a `Result` and takes `Send` parameters.
+        run_app(Backend::Local(queues), Some(tx), names, tas, rubric, rooms).await
+    };
+    let _ = std::fs::remove_file(&socket);
+    result
+}
+
+/// Sends one `ctl` action to a running instance and prints its reply.
+fn run_ctl(socket: &Path, queue: Option<String>, action: CtlAction) -> std::io::Result<()> {
+    let command = match &action {
+        CtlAction::List => control::Command::List,
+        CtlAction::Next { ta } => control::Command::Next {
+            ta: ta.clone().unwrap_or_else(|| std::env::var("USER").unwrap_or_else(|_| "TA".to_string())),
+        },
+        CtlAction::Remove { id } => control::Command::Remove { id: id.clone() },
+        CtlAction::Open => control::Command::Open,
+        CtlAction::Close => control::Command::Close,
+        CtlAction::Stats => control::Command::Stats,
+        CtlAction::NewSession => control::Command::StartSession,
+    };
+    let data = match control::send(socket, &control::Request { queue, command })? {
+        control::Reply::Ok(data) => data,
+        control::Reply::Error(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+    let listing = |data| serde_json::from_value::<control::Listing>(data).map_err(std::io::Error::other);
+    match action {
+        CtlAction::List => {
+            let listings: Vec<control::Listing> = serde_json::from_value(data).map_err(std::io::Error::other)?;
+            if listings.is_empty() {
+                println!("Nobody is in line.");
+            }
+            for (i, x) in listings.iter().enumerate() {
+                let status = x.helper.as_ref().map(|ta| format!("with {}", ta)).unwrap_or_else(|| "waiting".to_string());
+                let joined = x.joined_at.with_timezone(&chrono::Local).format("%-I:%M %p");
+                println!("{:>3}. {}  {} ({}) - {}, joined {}, {}", i + 1, x.id, x.names, x.csid, x.topic, joined, status);
+            }
+        }
+        CtlAction::Next { .. } => {
+            let x = listing(data)?;
+            println!("Now helping {} ({}) - {}", x.names, x.csid, x.topic);
+        }
+        CtlAction::Stats => println!("{}", serde_json::to_string_pretty(&data)?),
+        CtlAction::Remove { .. } | CtlAction::Open | CtlAction::Close | CtlAction::NewSession => {
+            println!("Done.")
+        }
+    }
+    Ok(())
+}
+
+/// Runs the TUI against the instance listening on `socket`, which owns the
+/// queues and serves students. The TAs on duty are added to each queue.
+async fn run_attached(
+    socket: PathBuf,
+    on_duty: Vec<Ta>,
+    tas: Vec<String>,
+    rubric: Option<Rubric>,
+    rooms: Option<Rooms>,
+) -> std::io::Result<()> {
+    let backend = Backend::Remote(socket.clone());
+    let exit = |e: String| -> ! {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    };
+    let names: Vec<String> = backend
+        .send("", control::Command::Queues)
+        .and_then(|data| serde_json::from_value(data).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| exit(e));
+    for name in &names {
+        for ta in &on_duty {
+            backend.send(name, control::Command::AddTa { ta: ta.clone() }).unwrap_or_else(|e| exit(e));
+        }
+    }
+    let result = run_app(backend, None, names.clone(), tas, rubric, rooms).await;
+    // The instance may already be gone, in which case nobody is on duty anyway
+    let backend = Backend::Remote(socket);
+    for name in &names {
+        for ta in &on_duty {
+            let _ = backend.send(name, control::Command::OffDuty { ta: ta.name.clone() });
+        }
+    }
+    result
+}
+
+/// Serves until SIGTERM or Ctrl-C, then stops the server and saves every
+/// queue. Staff manage the queues through the admin API.
+async fn run_headless(
+    tx: mpsc::Sender<ServerControlMessage>,
+    mut server: JoinHandle<std::io::Result<()>>,
+    queues: Queues,
+) -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tx.send(ServerControlMessage::Start).await.expect("Server task is running");
+    tokio::select! {
+        _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("Received Ctrl-C, shutting down"),
+        // Only returns early when the server could not start
+        result = &mut server => return result.expect("Server task panicked"),
+    }
+
+    tx.send(ServerControlMessage::Stop).await.expect("Server task is running");
+    drop(tx);
+    server.await.expect("Server task panicked")?;
+    for (name, queue_ref) in queues.iter() {
+        queue_ref.lock().unwrap().save()?;
+        info!("Saved queue {}", name);
+    }
+    Ok(())
+}
+
+/// The queue named `name`, or the default queue. Exits if there is no such queue.
+fn find_spec(specs: &[QueueSpec], name: Option<String>) -> &QueueSpec {
+    match name {
+        Some(name) => specs.iter().find(|spec| spec.name == name).unwrap_or_else(|| {
+            eprintln!("There is no queue named {}.", name);
+            std::process::exit(1);
+        }),
+        None => &specs[0],
+    }
 }
 
 
@@ -38,7 +520,7 @@ use crossterm::{
     event::{self, KeyCode, KeyEvent, read},
 };
 use ratatui::{
-    widgets::{Block, Borders, List, ListItem, ListState},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Tabs},
     layout::{Constraint, Direction, Layout},
     text::{Span, Line},
     style::{Style, Color},
@@ -47,119 +529,389 @@ use ratatui::{
     prelude::Modifier,
 };
 
+/// A checkoff being graded against its lab's rubric.
+struct Grading {
+    id: String,
+    lab: String,
+    items: Vec<ItemResult>,
+    selected: usize,
+}
+
+/// Where the TUI's queues live: in this process, or in a running instance
+/// reached through its control socket.
+enum Backend {
+    Local(Queues),
+    Remote(PathBuf),
+}
+
+impl Backend {
+    fn send(&self, queue: &str, command: control::Command) -> Result<serde_json::Value, String> {
+        let request = control::Request { queue: Some(queue.to_string()), command };
+        let reply = match self {
+            Backend::Local(queues) => control::handle(queues, request),
+            Backend::Remote(socket) => control::send(socket, &request).map_err(|e| e.to_string())?,
+        };
+        match reply {
+            control::Reply::Ok(data) => Ok(data),
+            control::Reply::Error(message) => Err(message),
+        }
+    }
+
+    fn view(&self, queue: &str) -> Result<View, String> {
+        let data = self.send(queue, control::Command::View)?;
+        serde_json::from_value(data).map_err(|e| e.to_string())
+    }
+}
+
+/// Runs the TUI against `names` queues. Server controls are only shown when
+/// this process runs the server, i.e. `tx` is set.
 async fn run_app(
-    tx: mpsc::Sender<ServerControlMessage>,
-    queue_ref: Arc<Mutex<Queue>>,
+    backend: Backend,
+    tx: Option<mpsc::Sender<ServerControlMessage>>,
+    names: Vec<String>,
+    tas: Vec<String>,
+    rubric: Option<Rubric>,
+    rooms: Option<Rooms>,
 ) -> std::io::Result<()> {
+    let mut view = backend.view(&names[0]).map_err(std::io::Error::other)?;
+
     enable_raw_mode()?;
     let mut stdout = std::io::stdout();
     execute!(stdout, EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    let backend_ui = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend_ui)?;
 
     let mut server_running = false;
     let mut list_state = ListState::default();
     list_state.select(Some(0)); // Start with the first student selected
+    let mut current_ta = 0;
+    let mut current_queue = 0;
+    let mut grading: Option<Grading> = None;
+    // Students marked with 'm' to merge into a group session
+    let mut marked: Vec<String> = vec![];
+    let mut location_input: Option<String> = None;
+    let mut show_map = false;
+    // Last failed action or lost connection, cleared by the next key press
+    let mut status: Option<String> = None;
 
     loop {
+        let queue_name = &names[current_queue];
+        match backend.view(queue_name) {
+            Ok(fresh) => view = fresh,
+            Err(e) => status = Some(e),
+        }
+        let last = view.students.len().saturating_sub(1);
+        list_state.select(list_state.selected().map(|i| i.min(last)));
+
         terminal.draw(|f| {
             let size = f.size();
             let block = Block::default().title("Server Control").borders(Borders::ALL);
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(5), Constraint::Percentage(95)].as_ref())
+                .constraints([Constraint::Length(7), Constraint::Length(3), Constraint::Min(0)].as_ref())
                 .split(size);
+            let body = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+                .split(chunks[2]);
+            let side = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                .split(body[1]);
 
-            let control_items = if !server_running {
-                vec![
+            let mut control_items = match (&backend, server_running) {
+                (Backend::Remote(socket), _) => vec![
+                    ListItem::new(Span::styled(format!("Attached to {}", socket.display()), Style::default().fg(Color::Yellow))),
+                    ListItem::new(Span::styled("Press 'q' to quit", Style::default().fg(Color::Green))),
+                ],
+                (Backend::Local(_), false) => vec![
                     ListItem::new(Span::styled("Press 's' to start the server", Style::default().fg(Color::Yellow))),
                     ListItem::new(Span::styled("Press 'q' to quit", Style::default().fg(Color::Green))),
-                ]
-            } else {
-                vec![
+                ],
+                (Backend::Local(_), true) => vec![
                     ListItem::new(Span::styled("Press 'x' to stop the server", Style::default().fg(Color::LightRed))),
                     ListItem::new(Span::styled("Press 'q' to quit", Style::default().fg(Color::Green))),
-                ]
+                ],
             };
 
+            control_items.push(ListItem::new(Span::styled(
+                format!("Acting as {} - 'c' claim, 'r' resolve, 'u' requeue, 't' switch TA, 'g' grade checkoff, 'm' mark, 'j' group session, 'l' map, 'n' new session", tas[current_ta]),
+                Style::default().fg(Color::Cyan),
+            )));
+
+            control_items.push(match view.state {
+                QueueState::Open => ListItem::new(Span::styled("Queue is open - press 'o' to soft-close it", Style::default().fg(Color::Green))),
+                QueueState::SoftClosed => ListItem::new(Span::styled("Queue is soft-closed to new students - press 'o' to close it", Style::default().fg(Color::Yellow))),
+                QueueState::Closed => ListItem::new(Span::styled("Queue is closed - press 'o' to open it", Style::default().fg(Color::LightRed))),
+            });
+            if let Some(message) = &status {
+                control_items.push(ListItem::new(Span::styled(message.as_str(), Style::default().fg(Color::LightRed))));
+            }
+
             let control_list = List::new(control_items).block(block);
             f.render_widget(control_list, chunks[0]);
 
-            let queue = queue_ref.lock().unwrap();
+            let tabs = Tabs::new(names.clone())
+                .block(Block::default().title("Queues ('Tab' to switch)").borders(Borders::ALL))
+                .select(current_queue)
+                .highlight_style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Cyan));
+            f.render_widget(tabs, chunks[1]);
 
-            let block = Block::default().title("Queue").borders(Borders::ALL);
-            let items: Vec<_> = queue.students.iter().enumerate().map(|(i, student)| {
+            let block = Block::default().title(format!("Queue ({} - 'p' to change)", view.policy)).borders(Borders::ALL);
+            let items: Vec<_> = view.students.iter().enumerate().map(|(i, student)| {
+                let helper = match (student.helper(), &student.location) {
+                    (Some(ta), Some(location)) => format!(" [group with {} at {}]", ta, location),
+                    (Some(ta), None) => format!(" [with {}]", ta),
+                    _ => String::new(),
+                };
+                let helper = if marked.contains(&student.id) { format!("{} *", helper) } else { helper };
                 let content = if Some(i) == list_state.selected() {
                     vec![
-                        Line::from(Span::styled(format!("Name: {}", student.info.name), Style::default().add_modifier(Modifier::BOLD).fg(Color::LightBlue))),
+                        Line::from(Span::styled(format!("Name: {}{}", student.names(), helper), Style::default().add_modifier(Modifier::BOLD).fg(Color::LightBlue))),
                         Line::from(Span::styled(format!("ID: {}", student.id), Style::default().add_modifier(Modifier::BOLD).fg(Color::LightBlue))),
-                        Line::from(Span::styled(format!("CSID: {}", student.info.csid), Style::default().add_modifier(Modifier::BOLD).fg(Color::LightBlue))),
-                        Line::from(Span::styled(format!("Purpose: {:?}", student.info.purpose), Style::default().add_modifier(Modifier::BOLD).fg(Color::LightBlue))),
+                        Line::from(Span::styled(format!("CSID: {}{}{}", student.info.csid, student.info.section.as_ref().map(|x| format!(" (section {})", x)).unwrap_or_default(), student.members.iter().map(|x| format!(", {}", x.csid)).collect::<String>()), Style::default().add_modifier(Modifier::BOLD).fg(Color::LightBlue))),
+                        Line::from(Span::styled(match student.info.kind {
+                            RequestKind::Help => format!("Purpose: {:?}", student.info.purpose),
+                            RequestKind::Checkoff => format!("Checkoff: {}", student.info.lab),
+                        }, Style::default().add_modifier(Modifier::BOLD).fg(Color::LightBlue))),
                         Line::from(Span::styled(format!("Details: {}", student.info.details), Style::default().add_modifier(Modifier::BOLD).fg(Color::LightBlue))),
                         Line::from(Span::styled(format!("Steps: {}", student.info.steps), Style::default().add_modifier(Modifier::BOLD).fg(Color::LightBlue))),
                     ]
                 } else {
                     vec![
-                        Line::from(Span::styled(format!("Name: {}, ID: {}", student.info.name, student.id), Style::default().fg(Color::Gray))),
+                        Line::from(Span::styled(format!("Name: {}, ID: {}{}", student.names(), student.id, helper), Style::default().fg(if student.is_waiting() { Color::Gray } else { Color::Green }))),
                     ]
                 };
                 ListItem::new(content)
             }).collect();
 
             let list = List::new(items).block(block).highlight_style(Style::default().add_modifier(Modifier::REVERSED));
-            f.render_stateful_widget(list, chunks[1], &mut list_state);
+            f.render_stateful_widget(list, body[0], &mut list_state);
+
+            if show_map {
+                f.render_widget(Clear, body[0]);
+                f.render_widget(seating_map(&view.students, rooms.as_ref(), list_state.selected()), body[0]);
+            }
+
+            let block = Block::default().title("TAs").borders(Borders::ALL);
+            let ta_items: Vec<_> = view.tas.iter().map(|ta| {
+                let group = view.group(&ta.name);
+                let helping = match group.as_slice() {
+                    [] => "available".to_string(),
+                    [student] => format!("helping {}", student.names()),
+                    students => format!("helping a group of {}", students.len()),
+                };
+                let style = if ta.name == tas[current_ta] {
+                    Style::default().add_modifier(Modifier::BOLD).fg(Color::Cyan)
+                } else {
+                    Style::default().fg(Color::Gray)
+                };
+                ListItem::new(Span::styled(format!("{}: {}", ta.name, helping), style))
+            }).collect();
+            f.render_widget(List::new(ta_items).block(block), side[0]);
+
+            let stats = &view.stats;
+            let block = Block::default().title("Session Stats").borders(Borders::ALL);
+            let stat_items: Vec<_> = [
+                format!("Since: {}", stats.session_started.with_timezone(&chrono::Local).format("%a %H:%M")),
+                format!("Waiting: {}, being helped: {}", stats.waiting, stats.being_helped),
+                format!("Resolved: {} ({:.1}/hour)", stats.resolved, stats.throughput_per_hour),
+                format!("Wait: avg {:.0}m, median {:.0}m, p90 {:.0}m", stats.wait.average, stats.wait.median, stats.wait.p90),
+                format!("Help: avg {:.0}m, median {:.0}m, p90 {:.0}m", stats.help.average, stats.help.median, stats.help.p90),
+            ].into_iter().map(|line| ListItem::new(Span::styled(line, Style::default().fg(Color::Gray)))).collect();
+            f.render_widget(List::new(stat_items).block(block), side[1]);
+
+            if let Some(grading) = &grading {
+                let block = Block::default()
+                    .title(format!("Checkoff: {} - 'Space' toggle, 'Enter' save, 'Esc' cancel", grading.lab))
+                    .borders(Borders::ALL);
+                let items: Vec<_> = grading.items.iter().enumerate().map(|(i, result)| {
+                    let mark = if result.passed { "[x]" } else { "[ ]" };
+                    let style = if i == grading.selected {
+                        Style::default().add_modifier(Modifier::BOLD).fg(Color::Cyan)
+                    } else {
+                        Style::default()
+                    };
+                    ListItem::new(Span::styled(format!("{} {}", mark, result.item), style))
+                }).collect();
+                f.render_widget(Clear, body[0]);
+                f.render_widget(List::new(items).block(block), body[0]);
+            }
+
+            if let Some(input) = &location_input {
+                let block = Block::default()
+                    .title(format!("Group session for {} students - 'Enter' start, 'Esc' cancel", marked.len()))
+                    .borders(Borders::ALL);
+                let prompt = ListItem::new(Span::styled(format!("Meeting location: {}_", input), Style::default().fg(Color::Cyan)));
+                f.render_widget(Clear, body[0]);
+                f.render_widget(List::new(vec![prompt]).block(block), body[0]);
+            }
         })?;
 
         if let Ok(true) = event::poll(std::time::Duration::from_millis(500)) {
-            match read()? {
-                event::Event::Key(KeyEvent { code: KeyCode::Char('s'), .. }) if !server_running => {
-                    if tx.send(ServerControlMessage::Start).await.is_err() {
-                        info!("Failed to send start command");
-                        continue;
-                    }
-                    server_running = true;
-                },
-                event::Event::Key(KeyEvent { code: KeyCode::Char('x'), .. }) if server_running => {
-                    if tx.send(ServerControlMessage::Stop).await.is_err() {
-                        info!("Failed to send stop command");
-                        continue;
-                    }
-                    server_running = false;
-                },
-                event::Event::Key(KeyEvent { code: KeyCode::Char('d'), .. }) => {
-                    // Remove selected student
-                    let mut queue = queue_ref.lock().unwrap();
-                    if queue.size() == 0 {
-                        continue;
-                    }
-                    if let Some(index) = list_state.selected() {
-                        queue.students.remove(index);
-                        queue.save().expect("Failed to save queue.");
-                        list_state.select(Some(index.saturating_sub(1))); // Adjust selection
-                    }
-                },
-                event::Event::Key(KeyEvent { code: KeyCode::Down, .. }) => {
-                    // Move selection down
-                    let queue = queue_ref.lock().unwrap();
-                    let next_index = match list_state.selected() {
-                        Some(i) => if i >= queue.students.len() - 1 { 0 } else { i + 1 },
-                        None => 0,
-                    };
-                    list_state.select(Some(next_index));
-                },
-                event::Event::Key(KeyEvent { code: KeyCode::Up, .. }) => {
-                    // Move selection up
-                    let queue = queue_ref.lock().unwrap();
-                    let prev_index = match list_state.selected() {
-                        Some(i) => if i == 0 { queue.students.len() - 1 } else { i - 1 },
-                        None => 0,
-                    };
-                    list_state.select(Some(prev_index));
-                },
-                event::Event::Key(KeyEvent { code: KeyCode::Char('q'), .. }) => {
-                    break;
-                },
-                _ => {}
+            let key = read()?;
+            if let event::Event::Key(_) = key {
+                status = None;
+            }
+            let ta = tas[current_ta].clone();
+            let selected = list_state.selected().and_then(|i| view.students.get(i));
+            let mut command = None;
+            if let (Some(current), event::Event::Key(KeyEvent { code, .. })) = (grading.as_mut(), &key) {
+                match code {
+                    KeyCode::Up => current.selected = current.selected.checked_sub(1).unwrap_or(current.items.len() - 1),
+                    KeyCode::Down => current.selected = (current.selected + 1) % current.items.len(),
+                    KeyCode::Char(' ') => current.items[current.selected].passed ^= true,
+                    KeyCode::Enter => {
+                        // Partners checked off together get the same grade
+                        command = Some(control::Command::Grade {
+                            id: current.id.clone(),
+                            ta,
+                            lab: current.lab.clone(),
+                            items: current.items.clone(),
+                        });
+                        grading = None;
+                    },
+                    KeyCode::Esc => grading = None,
+                    _ => {}
+                }
+            } else if let (Some(input), event::Event::Key(KeyEvent { code, .. })) = (location_input.as_mut(), &key) {
+                match code {
+                    KeyCode::Char(c) => input.push(*c),
+                    KeyCode::Backspace => { input.pop(); },
+                    KeyCode::Enter if !input.trim().is_empty() => {
+                        // Skip anyone who left or was claimed since being marked
+                        marked.retain(|id| view.students.iter().any(|x| x.id == *id && x.is_waiting()));
+                        command = Some(control::Command::Merge {
+                            ids: std::mem::take(&mut marked),
+                            ta,
+                            location: input.trim().to_string(),
+                        });
+                        location_input = None;
+                    },
+                    KeyCode::Esc => location_input = None,
+                    _ => {}
+                }
+            } else {
+                match key {
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('s'), .. }) if !server_running => {
+                        let Some(tx) = &tx else { continue };
+                        if tx.send(ServerControlMessage::Start).await.is_err() {
+                            info!("Failed to send start command");
+                            continue;
+                        }
+                        server_running = true;
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('x'), .. }) if server_running => {
+                        let Some(tx) = &tx else { continue };
+                        if tx.send(ServerControlMessage::Stop).await.is_err() {
+                            info!("Failed to send stop command");
+                            continue;
+                        }
+                        server_running = false;
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('d'), .. }) => {
+                        // Remove selected student
+                        if let Some(student) = selected {
+                            command = Some(control::Command::Remove { id: student.id.clone() });
+                            list_state.select(list_state.selected().map(|i| i.saturating_sub(1))); // Adjust selection
+                        }
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('c'), .. }) => {
+                        // Claim selected student for the current TA
+                        if let Some(student) = selected {
+                            command = Some(control::Command::Claim { id: student.id.clone(), ta });
+                        }
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('r'), .. }) => {
+                        // Resolve everyone the current TA is helping
+                        command = Some(control::Command::ResolveGroup { ta });
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('u'), .. }) => {
+                        // Put everyone the current TA is helping back in line
+                        command = Some(control::Command::RequeueGroup { ta });
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('m'), .. }) => {
+                        // Mark or unmark the selected student for a group session
+                        if let Some(student) = selected {
+                            match marked.iter().position(|id| *id == student.id) {
+                                Some(index) => { marked.remove(index); },
+                                None if student.is_waiting() => marked.push(student.id.clone()),
+                                None => {}
+                            }
+                        }
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('j'), .. }) if !marked.is_empty() => {
+                        location_input = Some(String::new());
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('g'), .. }) => {
+                        // Grade the checkoff the current TA is helping with
+                        let Some(student) = view.group(&ta).first().copied() else { continue };
+                        if student.info.kind != RequestKind::Checkoff {
+                            continue;
+                        }
+                        match rubric.as_ref().and_then(|rubric| rubric.lab(&student.info.lab)) {
+                            Some(lab) => {
+                                grading = Some(Grading {
+                                    id: student.id.clone(),
+                                    lab: lab.name.clone(),
+                                    items: lab.items.iter().map(|item| ItemResult { item: item.clone(), passed: false }).collect(),
+                                    selected: 0,
+                                });
+                            }
+                            None => status = Some(format!("No rubric for {}", student.info.lab)),
+                        }
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('l'), .. }) => {
+                        show_map = !show_map;
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('o'), .. }) => {
+                        let state = match view.state {
+                            QueueState::Open => QueueState::SoftClosed,
+                            QueueState::SoftClosed => QueueState::Closed,
+                            QueueState::Closed => QueueState::Open,
+                        };
+                        command = Some(control::Command::SetState { state });
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('p'), .. }) => {
+                        command = Some(control::Command::SetPolicy { policy: view.policy.next() });
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('n'), .. }) => {
+                        command = Some(control::Command::StartSession);
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Tab, .. }) => {
+                        current_queue = (current_queue + 1) % names.len();
+                        list_state.select(Some(0));
+                        marked.clear();
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('t'), .. }) => {
+                        current_ta = (current_ta + 1) % tas.len();
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Down, .. }) => {
+                        // Move selection down
+                        let next_index = match list_state.selected() {
+                            Some(i) => if i + 1 >= view.students.len() { 0 } else { i + 1 },
+                            None => 0,
+                        };
+                        list_state.select(Some(next_index));
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Up, .. }) => {
+                        // Move selection up
+                        let prev_index = match list_state.selected() {
+                            Some(i) => if i == 0 { view.students.len().saturating_sub(1) } else { i - 1 },
+                            None => 0,
+                        };
+                        list_state.select(Some(prev_index));
+                    },
+                    event::Event::Key(KeyEvent { code: KeyCode::Char('q'), .. }) => {
+                        break;
+                    },
+                    _ => {}
+                }
+            }
+            if let Some(command) = command {
+                if let Err(e) = backend.send(queue_name, command) {
+                    status = Some(e);
+                }
             }
         }
     }
@@ -167,4 +919,71 @@ async fn run_app(
     disable_raw_mode()?;
     execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// A grid of the selected student's room, or the first room, showing each
+/// waiting student's place in line under the active policy, and the TA
+/// helping everyone else. The selected student is highlighted.
+fn seating_map<'a>(students: &[StudentRequest], rooms: Option<&'a Rooms>, selected: Option<usize>) -> Paragraph<'a> {
+    let selected = selected.and_then(|i| students.get(i));
+    let Some(rooms) = rooms.filter(|rooms| !rooms.rooms.is_empty()) else {
+        let block = Block::default().title("Seating map ('l' for list)").borders(Borders::ALL);
+        return Paragraph::new("Start with --rooms to see where students are sitting.").block(block);
+    };
+    let room = selected
+        .and_then(|student| rooms.get(&student.info.room))
+        .unwrap_or(&rooms.rooms[0]);
+
+    // `students` lists everyone being helped before those waiting in policy
+    // order, so places in line are counted among waiting students only
+    let mut seats = vec![vec![None; room.columns]; room.rows];
+    let mut waiting = 0;
+    for student in students {
+        let label: String = match student.helper() {
+            Some(ta) => ta.chars().take(3).collect(),
+            None => {
+                waiting += 1;
+                waiting.to_string()
+            }
+        };
+        if rooms.get(&student.info.room).is_some_and(|x| x.name == room.name) {
+            if let Some((row, column)) = room.seat(&student.info.seat) {
+                seats[row][column] = Some((label, student));
+            }
+        }
+    }
+
+    let mut header = vec![Span::raw("   ")];
+    header.extend((0..room.columns).map(|column| Span::raw(format!("{:^5}", column + 1))));
+    let mut lines = vec![Line::from(header)];
+    for (row, cells) in seats.iter().enumerate() {
+        let mut spans = vec![Span::raw(format!("{}  ", Room::row_label(row)))];
+        for cell in cells {
+            spans.push(match cell {
+                Some((label, student)) => {
+                    let color = if student.is_waiting() { Color::Yellow } else { Color::Green };
+                    let mut style = Style::default().fg(color).add_modifier(Modifier::BOLD);
+                    if selected.is_some_and(|x| x.id == student.id) {
+                        style = style.add_modifier(Modifier::REVERSED);
+                    }
+                    Span::styled(format!("[{:>3}]", label), style)
+                }
+                None => Span::styled("[   ]", Style::default().fg(Color::DarkGray)),
+            });
+        }
+        lines.push(Line::from(spans));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(match selected {
+        Some(student) if !student.info.room.is_empty() => {
+            format!("{} is at {} {}", student.names(), student.info.room, student.info.seat)
+        }
+        Some(student) => format!("{} did not give a location", student.names()),
+        None => String::new(),
+    }));
+
+    let block = Block::default()
+        .title(format!("Seating map: {} ('l' for list)", room.name))
+        .borders(Borders::ALL);
+    Paragraph::new(lines).block(block)
+}
@@ -0,0 +1,180 @@
+use crate::history::Visit;
+use crate::queue::{Purpose, StudentRequest};
+use chrono::{DateTime, Datelike, Duration, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// How waiting students are ordered.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Policy {
+    /// First come, first served.
+    #[default]
+    Fifo,
+    /// Students who have not been helped yet today go first.
+    FirstVisitToday,
+    /// Students helped the fewest times since Monday go first.
+    FewestVisitsThisWeek,
+    /// Take turns between purposes so one kind of question can't starve the rest.
+    RoundRobin,
+}
+
+impl Policy {
+    pub const ALL: [Policy; 4] = [
+        Policy::Fifo,
+        Policy::FirstVisitToday,
+        Policy::FewestVisitsThisWeek,
+        Policy::RoundRobin,
+    ];
+
+    /// The policy after this one, for cycling through them in the TUI.
+    pub fn next(self) -> Policy {
+        let index = Policy::ALL.iter().position(|x| *x == self).unwrap();
+        Policy::ALL[(index + 1) % Policy::ALL.len()]
+    }
+
+    /// Orders `waiting`, which must already be in join order. Ties keep join
+    /// order.
+    pub fn order<'a>(
+        self,
+        mut waiting: Vec<&'a StudentRequest>,
+        visits: &[Visit],
+        now: DateTime<Local>,
+    ) -> Vec<&'a StudentRequest> {
+        match self {
+            Policy::Fifo => waiting,
+            Policy::FirstVisitToday => {
+                let today = now.date_naive();
+                let helped = helped_since(visits, |at| at.date_naive() >= today);
+                waiting.sort_by_key(|x| helped.contains_key(x.info.csid.as_str()));
+                waiting
+            }
+            Policy::FewestVisitsThisWeek => {
+                let monday = now.date_naive()
+                    - Duration::days(now.weekday().num_days_from_monday() as i64);
+                let helped = helped_since(visits, |at| at.date_naive() >= monday);
+                waiting.sort_by_key(|x| helped.get(x.info.csid.as_str()).copied().unwrap_or(0));
+                waiting
+            }
+            Policy::RoundRobin => {
+                // Purposes take turns in the order they first appear in line
+                let mut lines: Vec<(&Purpose, Vec<&StudentRequest>)> = vec![];
+                for request in waiting {
+                    match lines.iter_mut().find(|(p, _)| **p == request.info.purpose) {
+                        Some((_, line)) => line.push(request),
+                        None => lines.push((&request.info.purpose, vec![request])),
+                    }
+                }
+                let longest = lines.iter().map(|(_, line)| line.len()).max().unwrap_or(0);
+                (0..longest)
+                    .flat_map(|turn| lines.iter().filter_map(move |(_, line)| line.get(turn)))
+                    .copied()
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Number of times each CSID was helped in visits matching `when`.
+fn helped_since(
+    visits: &[Visit],
+    when: impl Fn(DateTime<Local>) -> bool,
+) -> HashMap<&str, usize> {
+    let mut helped = HashMap::new();
+    for visit in visits {
+        if visit.claimed_at.is_some() && when(visit.finished_at.with_timezone(&Local)) {
+            *helped.entry(visit.csid.as_str()).or_insert(0) += 1;
+        }
+    }
+    helped
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Policy::Fifo => "fifo",
+            Policy::FirstVisitToday => "first-visit",
+            Policy::FewestVisitsThisWeek => "fewest-visits",
+            Policy::RoundRobin => "round-robin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Policy::ALL
+            .into_iter()
+            .find(|policy| policy.to_string() == s.to_ascii_lowercase())
+            .ok_or_else(|| {
+                format!(
+                    "unknown policy '{}', expected fifo, first-visit, fewest-visits or round-robin",
+                    s
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::testing::{local, student, visit};
+
+    fn csids(ordered: Vec<&StudentRequest>) -> Vec<&str> {
+        ordered.iter().map(|x| x.info.csid.as_str()).collect()
+    }
+
+    #[test]
+    fn first_visit_puts_students_helped_today_last() {
+        let waiting = [student("111"), student("222"), student("333")];
+        let visits = [
+            visit("111", local(14, 9, 0), local(14, 9, 20)),
+            // Yesterday does not count
+            visit("333", local(13, 16, 0), local(13, 16, 20)),
+        ];
+        let ordered = Policy::FirstVisitToday.order(waiting.iter().collect(), &visits, local(14, 12, 0));
+        assert_eq!(csids(ordered), ["222", "333", "111"]);
+    }
+
+    #[test]
+    fn fewest_visits_counts_helped_visits_since_monday() {
+        let waiting = [student("111"), student("222"), student("333")];
+        let mut unclaimed = visit("333", local(13, 10, 0), local(13, 10, 30));
+        unclaimed.claimed_at = None;
+        let visits = [
+            visit("111", local(12, 0, 0), local(12, 0, 20)),
+            visit("111", local(13, 10, 0), local(13, 10, 20)),
+            visit("222", local(13, 11, 0), local(13, 11, 20)),
+            // The Friday before does not count
+            visit("333", local(9, 10, 0), local(9, 10, 20)),
+            visit("333", local(9, 11, 0), local(9, 11, 20)),
+            unclaimed,
+        ];
+        let ordered = Policy::FewestVisitsThisWeek.order(waiting.iter().collect(), &visits, local(14, 12, 0));
+        assert_eq!(csids(ordered), ["333", "222", "111"]);
+    }
+
+    #[test]
+    fn round_robin_takes_turns_between_purposes() {
+        let purposes = [
+            ("a", Purpose::Debugging),
+            ("b", Purpose::Debugging),
+            ("c", Purpose::ConceptualLab),
+            ("d", Purpose::Debugging),
+            ("e", Purpose::Other),
+        ];
+        let waiting: Vec<StudentRequest> = purposes
+            .into_iter()
+            .map(|(csid, purpose)| {
+                let mut request = student(csid);
+                request.info.purpose = purpose;
+                request
+            })
+            .collect();
+        let ordered = Policy::RoundRobin.order(waiting.iter().collect(), &[], local(14, 12, 0));
+        assert_eq!(csids(ordered), ["a", "c", "e", "b", "d"]);
+    }
+}
@@ -1,12 +1,17 @@
-use crossterm::queue;
+use crate::history::{self, History, Outcome, Visit};
+use crate::journal::{self, Entry, Event, Journal};
+use crate::policy::Policy;
+use chrono::{DateTime, Local, Utc};
+use log::warn;
 use serde::{Serialize, Deserialize};
-use serde_json;
-use std::fs::{read_to_string, File};
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum Purpose {
     ConceptualMaterial,
     ConceptualLab,
@@ -25,6 +30,15 @@ impl Purpose {
     }
 }
 
+/// What the student is in line for.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum RequestKind {
+    #[default]
+    Help,
+    /// Getting a lab checked off against its rubric.
+    Checkoff,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct StudentInfo {
     pub name: String,
@@ -32,6 +46,20 @@ pub struct StudentInfo {
     pub purpose: Purpose,
     pub details: String,
     pub steps: String,
+    /// Lab section from the course roster, when one is loaded.
+    #[serde(default)]
+    pub section: Option<String>,
+    #[serde(default)]
+    pub kind: RequestKind,
+    /// Lab to check off, for checkoff requests.
+    #[serde(default)]
+    pub lab: String,
+    /// Room the student is sitting in, when they are in person.
+    #[serde(default)]
+    pub room: String,
+    /// Seat or table in `room`, e.g. `B3`.
+    #[serde(default)]
+    pub seat: String,
 }
 
 impl StudentInfo {
@@ -42,14 +70,103 @@ impl StudentInfo {
             purpose,
             details,
             steps,
+            section: None,
+            kind: RequestKind::Help,
+            lab: String::new(),
+            room: String::new(),
+            seat: String::new(),
+        }
+    }
+}
+
+/// A staff member who can claim students from the queue.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Ta {
+    pub name: String,
+    /// Video-call link shown to students this TA claims, for online hours.
+    #[serde(default)]
+    pub meeting_link: Option<String>,
+}
+
+impl Ta {
+    pub fn new(name: String) -> Ta {
+        Ta { name, meeting_link: None }
+    }
+}
+
+/// A TA given on the command line as `name` or `name=meeting-link`.
+impl std::str::FromStr for Ta {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, link)) = s.split_once('=') else {
+            return Ok(Ta::new(s.to_string()));
+        };
+        if !link.starts_with("https://") && !link.starts_with("http://") {
+            return Err(format!("{}'s meeting link must be an http(s) URL", name));
         }
+        Ok(Ta {
+            name: name.to_string(),
+            meeting_link: Some(link.to_string()),
+        })
     }
 }
 
+/// A partner who attached to someone else's request with its invite code.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Member {
+    pub name: String,
+    pub csid: String,
+    /// Lets this member's browser act on the shared request.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secret: String,
+}
+
+impl Member {
+    pub fn new(name: String, csid: String) -> Member {
+        Member {
+            name,
+            csid,
+            secret: Uuid::new_v4().simple().to_string(),
+        }
+    }
+}
+
+/// Where a request is in its lifecycle. Resolved requests leave the queue.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub enum Status {
+    #[default]
+    Waiting,
+    BeingHelped { ta: String },
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct StudentRequest {
     pub info : StudentInfo,
     pub id: String,
+    #[serde(default)]
+    pub status: Status,
+    #[serde(default = "Utc::now")]
+    pub joined_at: DateTime<Utc>,
+    #[serde(default)]
+    pub claimed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub resolved_at: Option<DateTime<Utc>>,
+    /// Known only to the student who joined; required to act on the request.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secret: String,
+    /// Partners who joined this request after it was created.
+    #[serde(default)]
+    pub members: Vec<Member>,
+    /// Shared with partners so they can attach to this request.
+    #[serde(default)]
+    pub invite: String,
+    /// Where to meet the TA, for requests merged into a group session.
+    #[serde(default)]
+    pub location: Option<String>,
+    /// The claiming TA's video-call link, for online hours.
+    #[serde(default)]
+    pub meeting_link: Option<String>,
 }
 
 impl StudentRequest {
@@ -57,6 +174,114 @@ impl StudentRequest {
         StudentRequest {
             info,
             id: Uuid::new_v4().to_string(),
+            status: Status::Waiting,
+            joined_at: Utc::now(),
+            claimed_at: None,
+            resolved_at: None,
+            secret: Uuid::new_v4().simple().to_string(),
+            members: vec![],
+            invite: Uuid::new_v4().simple().to_string()[..8].to_string(),
+            location: None,
+            meeting_link: None,
+        }
+    }
+
+    /// A copy to show staff, without the secrets of the student or their
+    /// partners.
+    pub fn without_secrets(&self) -> StudentRequest {
+        StudentRequest {
+            secret: String::new(),
+            members: self
+                .members
+                .iter()
+                .map(|member| Member { secret: String::new(), ..member.clone() })
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Whether `secret` belongs to the student who joined or any partner.
+    pub fn authorizes(&self, secret: &str) -> bool {
+        let owner = !self.secret.is_empty() && self.secret == secret;
+        owner || self.members.iter().any(|member| member.secret == secret)
+    }
+
+    /// Whether `csid` is the student who joined or one of their partners.
+    pub fn includes(&self, csid: &str) -> bool {
+        let matches = |x: &str| x.trim().eq_ignore_ascii_case(csid.trim());
+        matches(&self.info.csid) || self.members.iter().any(|member| matches(&member.csid))
+    }
+
+    /// Everyone on the request, e.g. `Alice & Bob`.
+    pub fn names(&self) -> String {
+        let mut names = vec![self.info.name.as_str()];
+        names.extend(self.members.iter().map(|member| member.name.as_str()));
+        names.join(" & ")
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.status == Status::Waiting
+    }
+
+    /// Name of the TA currently helping this student, if any.
+    pub fn helper(&self) -> Option<&str> {
+        match &self.status {
+            Status::BeingHelped { ta } => Some(ta),
+            Status::Waiting => None,
+        }
+    }
+}
+
+/// Whether the queue is taking new students.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum QueueState {
+    #[default]
+    Open,
+    /// No new joins, but students already in line are still served.
+    SoftClosed,
+    /// Office hours are over.
+    Closed,
+}
+
+impl QueueState {
+    pub fn accepts_joins(&self) -> bool {
+        *self == QueueState::Open
+    }
+}
+
+/// Why a student could not join the queue.
+#[derive(Debug)]
+pub enum JoinError {
+    Closed,
+    /// The CSID already has an active request with this id.
+    Duplicate { id: String },
+    /// No request in line has this invite code.
+    UnknownInvite,
+    /// The request could not be written to the journal.
+    Io(io::Error),
+}
+
+/// Why a change to a request in line could not be made.
+#[derive(Debug)]
+pub enum QueueError {
+    /// No request in line has this id, or the caller may not act on it.
+    NotFound,
+    /// The request or TA is not in a state the change applies to, e.g.
+    /// claiming a student who is already being helped.
+    Conflict,
+    /// The change itself makes no sense, e.g. moving a student to position 0.
+    Invalid,
+    /// The change could not be written to the journal, so it was not made.
+    Io(io::Error),
+}
+
+impl std::fmt::Display for QueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueError::NotFound => write!(f, "That request is not in the queue."),
+            QueueError::Conflict => write!(f, "That request cannot be changed like that right now."),
+            QueueError::Invalid => write!(f, "That change is not valid."),
+            QueueError::Io(e) => write!(f, "The queue could not be saved: {}", e),
         }
     }
 }
@@ -68,72 +293,781 @@ pub struct IdQuery {
 }
 
 
+/// Number of journal entries to accumulate before compacting into a snapshot.
+const COMPACT_EVERY: usize = 100;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Queue {
     pub students: Vec<StudentRequest>,
+    #[serde(default)]
+    pub tas: Vec<Ta>,
+    /// Requests resolved since the current session started.
+    #[serde(default)]
+    pub resolved: Vec<StudentRequest>,
+    #[serde(default = "Utc::now")]
+    pub session_started: DateTime<Utc>,
+    #[serde(default)]
+    pub state: QueueState,
+    #[serde(default)]
+    pub policy: Policy,
+    /// Sequence number of the last event reflected in this snapshot.
+    #[serde(default)]
+    seq: u64,
+    /// TAs on duty this run, once per TUI they have open. Unlike `tas`, this
+    /// is not saved.
+    #[serde(skip)]
+    on_duty: Vec<String>,
     #[serde(skip)]
     path: PathBuf,
+    #[serde(skip)]
+    journal: Option<Journal>,
+    #[serde(skip)]
+    history: Option<History>,
+    #[serde(skip, default = "changes_channel")]
+    changes: broadcast::Sender<Event>,
+}
+
+fn changes_channel() -> broadcast::Sender<Event> {
+    broadcast::channel(64).0
 }
 
 
 impl Queue {
+    /// Loads the last snapshot and replays any journal entries written after
+    /// it, archiving visits they finished that did not reach the history. A
+    /// snapshot that exists but cannot be parsed is an error rather than an
+    /// empty queue.
     pub fn load(path: PathBuf) -> io::Result<Self> {
-        let data = read_to_string(&path).unwrap_or_else(|_| "{}".to_string());
-        let mut queue: Queue = serde_json::from_str(&data).unwrap_or_else(|_| Queue { students: vec![], path: path.clone() });
+        let mut queue = match read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Queue::new(path.clone()),
+            Err(e) => return Err(e),
+        };
         queue.path = path;
+
+        let mut history = History::load(history::path_for(&queue.path))?;
+        let journal_path = journal::path_for(&queue.path);
+        for entry in Journal::read(&journal_path)? {
+            if entry.seq <= queue.seq {
+                continue; // Already in the snapshot
+            }
+            match queue.apply(&entry) {
+                Ok(finished) => {
+                    // The instance may have stopped before archiving them
+                    for visit in finished {
+                        if !history.contains(&visit) {
+                            history.append(visit)?;
+                        }
+                    }
+                }
+                Err(_) => warn!("Skipping journal entry {} that no longer applies", entry.seq),
+            }
+            queue.seq = entry.seq;
+        }
+        queue.journal = Some(Journal::open(journal_path)?);
+        queue.history = Some(history);
+        queue.save()?;
         Ok(queue)
     }
 
-    pub fn save(&self) -> io::Result<()> {
+    /// Writes a snapshot and truncates the journal it covers.
+    pub fn save(&mut self) -> io::Result<()> {
         let data = serde_json::to_string_pretty(&self)?;
-        let mut file = File::create(&self.path)?;
-        file.write_all(data.as_bytes())
+        journal::write_atomic(&self.path, data.as_bytes())?;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.truncate()?;
+        }
+        Ok(())
+    }
+
+    /// Appends `event` to the journal, then applies it. Invalid events are
+    /// rejected before anything is written, and the queue only changes once
+    /// the entry is on disk. Requests leaving the queue are archived to the
+    /// history, one visit per member.
+    fn record(&mut self, event: Event) -> Result<(), QueueError> {
+        let entry = Entry {
+            seq: self.seq + 1,
+            at: Utc::now(),
+            event,
+        };
+        self.scratch().apply(&entry)?;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.append(&entry).map_err(QueueError::Io)?;
+        }
+        let finished = self.apply(&entry).expect("Event already applied to a copy");
+        self.seq = entry.seq;
+
+        // The entry is already durable, so these only cost an archived visit
+        // or an early compaction. Visits are archived first, since replaying
+        // the journal restores any that are missing until it is compacted.
+        if let Some(history) = self.history.as_mut() {
+            for visit in finished {
+                if let Err(e) = history.append(visit) {
+                    warn!("Could not write visit history: {}", e);
+                }
+            }
+        }
+        if self.journal.as_ref().is_some_and(|journal| journal.len >= COMPACT_EVERY) {
+            if let Err(e) = self.save() {
+                warn!("Could not compact the queue journal: {}", e);
+            }
+        }
+        // Nobody listening is fine
+        let _ = self.changes.send(entry.event);
+        Ok(())
     }
 
-    pub fn add(&mut self, request: StudentRequest) {
-        self.students.push(request);
-        self.save().expect("Failed to save queue.");
+    /// A copy of the in-memory queue without its journal or history, for
+    /// checking that an event applies.
+    fn scratch(&self) -> Queue {
+        Queue {
+            students: self.students.clone(),
+            tas: self.tas.clone(),
+            resolved: self.resolved.clone(),
+            session_started: self.session_started,
+            state: self.state,
+            policy: self.policy,
+            seq: self.seq,
+            ..Queue::new(self.path.clone())
+        }
     }
 
-    pub fn remove(&mut self, id: String) -> Result<(), ()> {
-        if let Some(index) = self.students.iter().position(|x| x.id == id) {
-            self.students.remove(index);
-            self.save().expect("Failed to save queue.");
-            Ok(())
-        } else {
-            Err(())
+    /// Applies `entry` to the in-memory queue, returning the visits it
+    /// finished.
+    fn apply(&mut self, entry: &Entry) -> Result<Vec<Visit>, QueueError> {
+        let mut finished = vec![];
+        match &entry.event {
+            Event::Join(request) => {
+                let mut request = StudentRequest::clone(request);
+                request.joined_at = entry.at;
+                self.students.push(request);
+            }
+            Event::Attach { id, member } => {
+                let index = self.index(id).ok_or(QueueError::NotFound)?;
+                self.students[index].members.push(member.clone());
+            }
+            Event::Leave { id } => {
+                let index = self.index(id).ok_or(QueueError::NotFound)?;
+                let request = self.students.remove(index);
+                finished = Visit::all(request, Outcome::Left, entry.at);
+            }
+            Event::Detach { id, csid } => {
+                let index = self.index(id).ok_or(QueueError::NotFound)?;
+                let request = &mut self.students[index];
+                let member = request
+                    .members
+                    .iter()
+                    .position(|member| member.csid == *csid)
+                    .ok_or(QueueError::NotFound)?;
+                let member = request.members.remove(member);
+                finished.push(Visit {
+                    name: member.name,
+                    csid: member.csid,
+                    ..Visit::new(request.clone(), Outcome::Left, entry.at)
+                });
+            }
+            Event::Remove { id } => {
+                let index = self.index(id).ok_or(QueueError::NotFound)?;
+                let request = self.students.remove(index);
+                finished = Visit::all(request, Outcome::Removed, entry.at);
+            }
+            Event::Claim { id, ta } => {
+                if self.helping(ta).is_some() {
+                    return Err(QueueError::Conflict);
+                }
+                let index = self.index(id).ok_or(QueueError::NotFound)?;
+                let link = self.meeting_link(ta);
+                let request = &mut self.students[index];
+                if !request.is_waiting() {
+                    return Err(QueueError::Conflict);
+                }
+                request.status = Status::BeingHelped { ta: ta.clone() };
+                request.claimed_at = Some(entry.at);
+                request.meeting_link = link;
+            }
+            Event::Resolve { id } => {
+                let index = self.index(id).ok_or(QueueError::NotFound)?;
+                if self.students[index].is_waiting() {
+                    return Err(QueueError::Conflict);
+                }
+                let mut request = self.students.remove(index);
+                request.resolved_at = Some(entry.at);
+                finished = Visit::all(request.clone(), Outcome::Resolved, entry.at);
+                self.resolved.push(request);
+            }
+            Event::Requeue { id } => {
+                let index = self.index(id).ok_or(QueueError::NotFound)?;
+                let request = &mut self.students[index];
+                if request.is_waiting() {
+                    return Err(QueueError::Conflict);
+                }
+                request.status = Status::Waiting;
+                request.claimed_at = None;
+                request.location = None;
+                request.meeting_link = None;
+            }
+            Event::Merge { ids, ta, location } => {
+                if ids.is_empty() {
+                    return Err(QueueError::Invalid);
+                }
+                if self.helping(ta).is_some() {
+                    return Err(QueueError::Conflict);
+                }
+                let indices = ids
+                    .iter()
+                    .map(|id| self.index(id).ok_or(QueueError::NotFound))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if indices.iter().any(|&i| !self.students[i].is_waiting()) {
+                    return Err(QueueError::Conflict);
+                }
+                let link = self.meeting_link(ta);
+                for index in indices {
+                    let request = &mut self.students[index];
+                    request.status = Status::BeingHelped { ta: ta.clone() };
+                    request.claimed_at = Some(entry.at);
+                    request.location = Some(location.clone());
+                    request.meeting_link = link.clone();
+                }
+            }
+            Event::Move { id, position } => {
+                let index = self.index(id).ok_or(QueueError::NotFound)?;
+                if *position == 0 {
+                    return Err(QueueError::Invalid);
+                }
+                if !self.students[index].is_waiting() {
+                    return Err(QueueError::Conflict);
+                }
+                let request = self.students.remove(index);
+                let target = self
+                    .students
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, x)| x.is_waiting())
+                    .nth(position - 1)
+                    .map_or(self.students.len(), |(i, _)| i);
+                self.students.insert(target, request);
+            }
+            Event::SetState(state) => {
+                // Nobody carries over to the next office hours
+                if *state == QueueState::Closed {
+                    let (waiting, helped) = std::mem::take(&mut self.students)
+                        .into_iter()
+                        .partition(|x: &StudentRequest| x.is_waiting());
+                    self.students = helped;
+                    for request in waiting {
+                        finished.extend(Visit::all(request, Outcome::Unserved, entry.at));
+                    }
+                }
+                self.state = *state;
+            }
+            Event::SetPolicy(policy) => self.policy = *policy,
+            Event::StartSession => {
+                self.resolved.clear();
+                self.session_started = entry.at;
+            }
+            Event::AddTa(ta) => match self.tas.iter_mut().find(|x| x.name == ta.name) {
+                Some(known) if ta.meeting_link.is_some() && known.meeting_link != ta.meeting_link => {
+                    known.meeting_link = ta.meeting_link.clone();
+                }
+                Some(_) => return Err(QueueError::Conflict),
+                None => self.tas.push(ta.clone()),
+            },
         }
+        Ok(finished)
     }
 
-    pub fn position(&self, id: String) -> Result<usize, ()> {
-        self.students.iter().position(|x| x.id == id).map(|pos| pos + 1).ok_or(())
+    /// Notifies the receiver of every event applied from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.changes.subscribe()
+    }
+
+    fn index(&self, id: &str) -> Option<usize> {
+        self.students.iter().position(|x| x.id == id)
+    }
+
+    /// Adds a request to the back of the line. Each CSID may only have one
+    /// active request at a time.
+    pub fn add(&mut self, request: StudentRequest) -> Result<(), JoinError> {
+        if !self.state.accepts_joins() {
+            return Err(JoinError::Closed);
+        }
+        if let Some(existing) = self.find_by_csid(&request.info.csid) {
+            return Err(JoinError::Duplicate { id: existing.id.clone() });
+        }
+        match self.record(Event::Join(Box::new(request))) {
+            Err(QueueError::Io(e)) => Err(JoinError::Io(e)),
+            result => {
+                result.expect("Joins always apply.");
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds a partner to the request with `invite`, returning the request's
+    /// id.
+    pub fn attach(&mut self, invite: &str, member: Member) -> Result<String, JoinError> {
+        if let Some(existing) = self.find_by_csid(&member.csid) {
+            return Err(JoinError::Duplicate { id: existing.id.clone() });
+        }
+        let id = self
+            .students
+            .iter()
+            .find(|x| !x.invite.is_empty() && x.invite == invite)
+            .map(|x| x.id.clone())
+            .ok_or(JoinError::UnknownInvite)?;
+        match self.record(Event::Attach { id: id.clone(), member }) {
+            Err(QueueError::Io(e)) => Err(JoinError::Io(e)),
+            result => {
+                result.expect("Attaching to a request in line always applies.");
+                Ok(id)
+            }
+        }
+    }
+
+    /// Removes a request on behalf of staff.
+    pub fn remove(&mut self, id: String) -> Result<(), QueueError> {
+        self.record(Event::Remove { id })
+    }
+
+    /// Takes whoever holds `secret` off request `id`. The student who joined
+    /// takes the whole request with them, while a partner only detaches
+    /// themselves.
+    pub fn leave(&mut self, id: String, secret: Option<&str>) -> Result<(), QueueError> {
+        let request = self.find(&id).ok_or(QueueError::NotFound)?;
+        let secret = secret.ok_or(QueueError::NotFound)?;
+        if !request.secret.is_empty() && request.secret == secret {
+            return self.record(Event::Leave { id });
+        }
+        let csid = request
+            .members
+            .iter()
+            .find(|member| member.secret == secret)
+            .map(|member| member.csid.clone())
+            .ok_or(QueueError::NotFound)?;
+        self.record(Event::Detach { id, csid })
+    }
+
+    pub fn find(&self, id: &str) -> Option<&StudentRequest> {
+        self.students.iter().find(|x| x.id == id)
+    }
+
+    /// The active request for `csid`, if the student is already in line
+    /// on their own or as a partner.
+    pub fn find_by_csid(&self, csid: &str) -> Option<&StudentRequest> {
+        self.students.iter().find(|x| x.includes(csid))
+    }
+
+    /// Checks that `secret` belongs to the request `id`.
+    pub fn authorize(&self, id: &str, secret: Option<&str>) -> Result<(), QueueError> {
+        match (self.find(id), secret) {
+            (Some(request), Some(secret)) if request.authorizes(secret) => Ok(()),
+            _ => Err(QueueError::NotFound),
+        }
+    }
+
+    /// Position among waiting students under the active policy, starting at
+    /// 1. A student who is currently being helped is at position 0.
+    pub fn position(&self, id: String) -> Result<usize, QueueError> {
+        let request = self.find(&id).ok_or(QueueError::NotFound)?;
+        if !request.is_waiting() {
+            return Ok(0);
+        }
+        self.ordered_waiting()
+            .iter()
+            .position(|x| x.id == id)
+            .map(|pos| pos + 1)
+            .ok_or(QueueError::NotFound)
+    }
+
+    /// Waiting students in join order.
+    pub fn waiting(&self) -> impl Iterator<Item = &StudentRequest> {
+        self.students.iter().filter(|x| x.is_waiting())
+    }
+
+    /// Waiting students in the order the active policy will serve them.
+    pub fn ordered_waiting(&self) -> Vec<&StudentRequest> {
+        self.policy
+            .order(self.waiting().collect(), self.visits(), Local::now())
+    }
+
+    /// Students being helped, followed by everyone waiting in policy order.
+    pub fn ordered(&self) -> Vec<&StudentRequest> {
+        let mut ordered: Vec<_> = self.students.iter().filter(|x| !x.is_waiting()).collect();
+        ordered.extend(self.ordered_waiting());
+        ordered
+    }
+
+    /// Every archived visit, oldest first.
+    pub fn visits(&self) -> &[Visit] {
+        self.history.as_ref().map_or(&[], |history| &history.visits)
+    }
+
+    /// The student `ta` is currently helping, if any.
+    pub fn helping(&self, ta: &str) -> Option<&StudentRequest> {
+        self.students.iter().find(|x| x.helper() == Some(ta))
+    }
+
+    fn meeting_link(&self, ta: &str) -> Option<String> {
+        self.tas.iter().find(|x| x.name == ta)?.meeting_link.clone()
+    }
+
+    /// Registers a TA, or updates the meeting link of one already known.
+    pub fn add_ta(&mut self, ta: Ta) {
+        // Registering a TA who is already known changes nothing
+        if let Err(QueueError::Io(e)) = self.record(Event::AddTa(ta)) {
+            warn!("Could not register TA: {}", e);
+        }
+    }
+
+    /// Registers `ta` and counts them as on duty until they go off duty or
+    /// this instance exits. Each TUI a TA has open counts once.
+    pub fn go_on_duty(&mut self, ta: Ta) {
+        self.on_duty.push(ta.name.clone());
+        self.add_ta(ta);
+    }
+
+    /// Undoes one `go_on_duty` for `ta`, e.g. when their TUI detaches.
+    pub fn go_off_duty(&mut self, ta: &str) {
+        if let Some(index) = self.on_duty.iter().position(|x| x == ta) {
+            self.on_duty.remove(index);
+        }
+    }
+
+    /// Number of TAs on duty this run, counting anyone helping a student.
+    pub fn tas_on_duty(&self) -> usize {
+        let mut names: Vec<&str> = self.on_duty.iter().map(String::as_str).collect();
+        names.extend(self.students.iter().filter_map(|x| x.helper()));
+        names.sort_unstable();
+        names.dedup();
+        names.len()
+    }
+
+    /// Marks a waiting student as being helped by `ta`. A TA can only help
+    /// one student at a time.
+    pub fn claim(&mut self, id: String, ta: &str) -> Result<(), QueueError> {
+        self.record(Event::Claim { id, ta: ta.to_string() })
+    }
+
+    /// Finishes helping a student, removing them from the queue.
+    pub fn resolve(&mut self, id: String) -> Result<(), QueueError> {
+        self.record(Event::Resolve { id })
+    }
+
+    /// Claims several waiting students for one group session with `ta` at
+    /// `location`. Nobody is claimed unless all of them are waiting.
+    pub fn merge(&mut self, ids: Vec<String>, ta: &str, location: String) -> Result<(), QueueError> {
+        self.record(Event::Merge { ids, ta: ta.to_string(), location })
+    }
+
+    /// Everyone `ta` is helping, which is more than one student during a
+    /// group session.
+    pub fn group(&self, ta: &str) -> Vec<&StudentRequest> {
+        self.students.iter().filter(|x| x.helper() == Some(ta)).collect()
+    }
+
+    /// Resolves everyone `ta` is helping.
+    pub fn resolve_group(&mut self, ta: &str) -> Result<(), QueueError> {
+        let ids: Vec<String> = self.group(ta).iter().map(|x| x.id.clone()).collect();
+        if ids.is_empty() {
+            return Err(QueueError::Conflict);
+        }
+        for id in ids {
+            self.resolve(id)?;
+        }
+        Ok(())
+    }
+
+    /// Puts a student who is being helped back into the waiting line at
+    /// their original spot.
+    pub fn requeue(&mut self, id: String) -> Result<(), QueueError> {
+        self.record(Event::Requeue { id })
+    }
+
+    /// Moves a waiting student to `position` in join order, starting at 1.
+    /// Policies other than FIFO may still serve them in a different order.
+    pub fn reorder(&mut self, id: String, position: usize) -> Result<(), QueueError> {
+        self.record(Event::Move { id, position })
+    }
+
+    /// Opens or closes the queue to new students. Closing it archives everyone
+    /// still waiting, so they can join again next time.
+    pub fn set_state(&mut self, state: QueueState) -> Result<(), QueueError> {
+        self.record(Event::SetState(state))
+    }
+
+    pub fn set_policy(&mut self, policy: Policy) -> Result<(), QueueError> {
+        self.record(Event::SetPolicy(policy))
+    }
+
+    /// Starts a new office-hours session, resetting session statistics.
+    pub fn start_session(&mut self) -> Result<(), QueueError> {
+        self.record(Event::StartSession)
     }
 }
 
 impl Queue {
     pub fn new(path: PathBuf) -> Self {
-        Queue { students: vec![], path }
+        Queue {
+            students: vec![],
+            tas: vec![],
+            resolved: vec![],
+            session_started: Utc::now(),
+            state: QueueState::Open,
+            policy: Policy::Fifo,
+            seq: 0,
+            path,
+            on_duty: vec![],
+            journal: None,
+            history: None,
+            changes: changes_channel(),
+        }
+    }
+
+    pub fn default_path() -> PathBuf {
+        let mut path = dirs::home_dir().expect("Could not find home directory");
+        path.push("queue.json");
+        path
     }
 
-    pub fn init(path: Option<PathBuf>) -> io::Result<Self> {
-        if let Some(path) = path {
-            Self::load(path)
-        } else {
-            let mut path = dirs::home_dir().expect("Could not find home directory");
-            path.push("queue.json");
-            
-            let queue = if !path.exists() {
-                Queue::new(path.clone())
-            } else {
-                Queue::load(path.clone()).expect("Failed to load queue")
-            };
-            queue.save().expect("Failed to save queue");
-            Ok(queue)
+    /// Where a named queue is kept when no path is given for it.
+    pub fn default_path_for(name: &str) -> PathBuf {
+        let mut path = dirs::home_dir().expect("Could not find home directory");
+        path.push(format!("queue-{}.json", name));
+        path
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Every queue served by one instance, looked up by name. The first queue is
+/// the default one, served at the site root.
+#[derive(Clone, Debug)]
+pub struct Queues {
+    entries: Vec<(String, Arc<Mutex<Queue>>)>,
+}
+
+impl Queues {
+    pub fn new(entries: Vec<(String, Queue)>) -> Queues {
+        assert!(!entries.is_empty(), "At least one queue is required");
+        Queues {
+            entries: entries
+                .into_iter()
+                .map(|(name, queue)| (name, Arc::new(Mutex::new(queue))))
+                .collect(),
         }
     }
 
-    pub fn size(&self) -> usize {
-        self.students.len()
+    pub fn get(&self, name: &str) -> Option<&Arc<Mutex<Queue>>> {
+        self.entries.iter().find(|(n, _)| n == name).map(|(_, queue)| queue)
+    }
+
+    pub fn first(&self) -> &Arc<Mutex<Queue>> {
+        &self.entries[0].1
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<Mutex<Queue>>)> {
+        self.entries.iter().map(|(name, queue)| (name.as_str(), queue))
+    }
+}
+
+/// Fixtures shared by the tests of several modules.
+#[cfg(test)]
+pub mod testing {
+    use super::{Purpose, Queue, StudentInfo, StudentRequest};
+    use crate::history::{History, Outcome, Visit};
+    use chrono::{DateTime, Local, TimeZone, Utc};
+    use std::path::PathBuf;
+
+    pub fn student(csid: &str) -> StudentRequest {
+        StudentRequest::new(StudentInfo::new(
+            csid.to_string(),
+            csid.to_string(),
+            Purpose::Debugging,
+            "details".to_string(),
+            "steps".to_string(),
+        ))
+    }
+
+    /// A fixed local time in the week starting Monday 12 October 2026, e.g.
+    /// `local(14, 12, 0)` for Wednesday at noon.
+    pub fn local(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 10, day, hour, minute, 0).unwrap()
+    }
+
+    /// A finished visit in which `csid` was helped from `claimed_at` to
+    /// `finished_at`.
+    pub fn visit(csid: &str, claimed_at: DateTime<Local>, finished_at: DateTime<Local>) -> Visit {
+        let mut visit = Visit::new(student(csid), Outcome::Resolved, finished_at.with_timezone(&Utc));
+        visit.joined_at = claimed_at.with_timezone(&Utc);
+        visit.claimed_at = Some(claimed_at.with_timezone(&Utc));
+        visit
+    }
+
+    /// A queue without a journal whose history holds `visits`.
+    pub fn with_visits(visits: Vec<Visit>) -> Queue {
+        let mut queue = Queue::new(PathBuf::from("queue.json"));
+        // Nothing is written, so the archive file never needs to exist
+        let mut history = History::load(PathBuf::from("/nonexistent/queue.history")).unwrap();
+        history.visits = visits;
+        queue.history = Some(history);
+        queue
+    }
+
+    /// An empty directory for one test's files, e.g. a queue snapshot and
+    /// its journal.
+    pub fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("oh-queue-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("Temporary directory is writable");
+        dir
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::testing::{student, temp_dir};
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    fn csids(queue: &Queue) -> Vec<&str> {
+        queue.students.iter().map(|x| x.info.csid.as_str()).collect()
+    }
+
+    #[test]
+    fn staff_copies_leave_out_secrets() {
+        let mut request = student("111");
+        request.members.push(Member::new("222".to_string(), "222".to_string()));
+        let shown = serde_json::to_string(&request.without_secrets()).unwrap();
+        assert!(!shown.contains("secret"));
+
+        let saved: StudentRequest = serde_json::from_str(&serde_json::to_string(&request).unwrap()).unwrap();
+        assert_eq!(saved.secret, request.secret);
+        assert_eq!(saved.members[0].secret, request.members[0].secret);
+    }
+
+    #[test]
+    fn closing_archives_students_still_waiting() {
+        let path = temp_dir("close").join("queue.json");
+        let mut queue = Queue::load(path).unwrap();
+        let helped = student("111");
+        let id = helped.id.clone();
+        queue.add(helped).unwrap();
+        queue.add(student("222")).unwrap();
+        queue.claim(id, "alice").unwrap();
+        queue.set_state(QueueState::Closed).unwrap();
+
+        assert_eq!(csids(&queue), ["111"]);
+        let archived: Vec<_> = queue.visits().iter().map(|x| (x.csid.as_str(), &x.outcome)).collect();
+        assert_eq!(archived, [("222", &Outcome::Unserved)]);
+        queue.set_state(QueueState::Open).unwrap();
+        assert!(queue.add(student("222")).is_ok());
+    }
+
+    #[test]
+    fn a_partner_leaving_only_detaches_themselves() {
+        let path = temp_dir("detach").join("queue.json");
+        let mut queue = Queue::load(path).unwrap();
+        let request = student("111");
+        let (id, invite, secret) = (request.id.clone(), request.invite.clone(), request.secret.clone());
+        queue.add(request).unwrap();
+        let partner = Member::new("222".to_string(), "222".to_string());
+        let partner_secret = partner.secret.clone();
+        queue.attach(&invite, partner).unwrap();
+        queue.claim(id.clone(), "alice").unwrap();
+
+        queue.leave(id.clone(), Some(&partner_secret)).unwrap();
+        let request = queue.find(&id).unwrap();
+        assert!(request.members.is_empty());
+        assert_eq!(request.helper(), Some("alice"));
+        let left: Vec<_> = queue.visits().iter().map(|x| (x.csid.as_str(), &x.outcome)).collect();
+        assert_eq!(left, [("222", &Outcome::Left)]);
+
+        assert!(matches!(queue.leave(id.clone(), Some(&partner_secret)), Err(QueueError::NotFound)));
+        queue.leave(id, Some(&secret)).unwrap();
+        assert!(queue.students.is_empty());
+    }
+
+    #[test]
+    fn load_replays_only_entries_after_the_snapshot() {
+        let path = temp_dir("replay").join("queue.json");
+        let mut queue = Queue::load(path.clone()).unwrap();
+        queue.add(student("111")).unwrap();
+        queue.add(student("222")).unwrap();
+        let written = Journal::read(&journal::path_for(&path)).unwrap();
+        queue.save().unwrap();
+        drop(queue);
+
+        // As if the instance stopped after the snapshot but before the journal
+        // was truncated, then recorded one more join
+        let mut journal = Journal::open(journal::path_for(&path)).unwrap();
+        for entry in &written {
+            journal.append(entry).unwrap();
+        }
+        let entry = Entry {
+            seq: 3,
+            at: Utc::now(),
+            event: Event::Join(Box::new(student("333"))),
+        };
+        journal.append(&entry).unwrap();
+
+        let queue = Queue::load(path).unwrap();
+        assert_eq!(csids(&queue), ["111", "222", "333"]);
+        assert_eq!(queue.seq, 3);
+    }
+
+    #[test]
+    fn load_archives_visits_missing_from_the_history() {
+        let path = temp_dir("archive").join("queue.json");
+        let mut queue = Queue::load(path.clone()).unwrap();
+        let (first, second) = (student("111"), student("222"));
+        let (first_id, second_id) = (first.id.clone(), second.id.clone());
+        queue.add(first).unwrap();
+        queue.add(second).unwrap();
+        queue.remove(first_id).unwrap();
+        // As if the instance stopped before archiving the second visit
+        queue.history = None;
+        queue.remove(second_id).unwrap();
+        drop(queue);
+
+        let queue = Queue::load(path).unwrap();
+        let archived: Vec<_> = queue.visits().iter().map(|x| x.csid.as_str()).collect();
+        assert_eq!(archived, ["111", "222"]);
+    }
+
+    #[test]
+    fn load_ignores_a_torn_final_entry() {
+        let path = temp_dir("torn").join("queue.json");
+        let mut queue = Queue::load(path.clone()).unwrap();
+        queue.add(student("111")).unwrap();
+        drop(queue);
+        let mut file = OpenOptions::new().append(true).open(journal::path_for(&path)).unwrap();
+        file.write_all(br#"{"seq":2,"at":"2026-"#).unwrap();
+
+        let mut queue = Queue::load(path.clone()).unwrap();
+        assert_eq!(csids(&queue), ["111"]);
+        // The torn line is gone, so new entries replay after a restart
+        queue.add(student("222")).unwrap();
+        drop(queue);
+        assert_eq!(csids(&Queue::load(path).unwrap()), ["111", "222"]);
+    }
+
+    #[test]
+    fn compaction_truncates_the_journal() {
+        let path = temp_dir("compact").join("queue.json");
+        let journal_path = journal::path_for(&path);
+        let mut queue = Queue::load(path.clone()).unwrap();
+        for i in 1..COMPACT_EVERY {
+            queue.add_ta(Ta::new(format!("ta{}", i)));
+        }
+        assert_eq!(Journal::read(&journal_path).unwrap().len(), COMPACT_EVERY - 1);
+
+        queue.add_ta(Ta::new("last".to_string()));
+        assert!(Journal::read(&journal_path).unwrap().is_empty());
+        drop(queue);
+        assert_eq!(Queue::load(path).unwrap().tas.len(), COMPACT_EVERY);
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A lab room laid out as a grid of seats, e.g.
+///
+/// ```toml
+/// [[room]]
+/// name = "Soda 271"
+/// rows = 4
+/// columns = 6
+/// ```
+///
+/// Seats are labelled by row letter and column number, from `A1` at the front
+/// left.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Room {
+    pub name: String,
+    pub rows: usize,
+    pub columns: usize,
+}
+
+impl Room {
+    /// The zero-based row and column of a seat label like `B3`.
+    pub fn seat(&self, label: &str) -> Option<(usize, usize)> {
+        let label = label.trim().to_ascii_uppercase();
+        let mut chars = label.chars();
+        let row = chars.next()?;
+        if !row.is_ascii_uppercase() {
+            return None;
+        }
+        let row = (row as u8 - b'A') as usize;
+        let column = chars.as_str().parse::<usize>().ok()?.checked_sub(1)?;
+        (row < self.rows && column < self.columns).then_some((row, column))
+    }
+
+    /// The letter seats in `row` are labelled with.
+    pub fn row_label(row: usize) -> char {
+        (b'A' + row as u8) as char
+    }
+}
+
+/// Every room students can say they are sitting in.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Rooms {
+    #[serde(rename = "room", default)]
+    pub rooms: Vec<Room>,
+}
+
+impl Rooms {
+    pub fn load(path: &Path) -> Result<Rooms, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read room layout {}: {}", path.display(), e))?;
+        let rooms: Rooms = toml::from_str(&data)
+            .map_err(|e| format!("Invalid room layout {}: {}", path.display(), e))?;
+        for room in &rooms.rooms {
+            if room.rows == 0 || room.rows > 26 || room.columns == 0 {
+                return Err(format!(
+                    "Room {} must have between 1 and 26 rows and at least one column",
+                    room.name
+                ));
+            }
+        }
+        Ok(rooms)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Room> {
+        self.rooms.iter().find(|room| room.name.eq_ignore_ascii_case(name.trim()))
+    }
+}
@@ -0,0 +1,38 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// One enrolled student, as listed in the roster CSV.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Enrollment {
+    pub csid: String,
+    pub name: String,
+    pub section: String,
+}
+
+/// The course roster, loaded from a CSV with `csid,name,section` columns.
+#[derive(Clone, Debug, Default)]
+pub struct Roster {
+    students: HashMap<String, Enrollment>,
+}
+
+impl Roster {
+    pub fn load(path: &Path) -> Result<Roster, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("Could not read roster {}: {}", path.display(), e))?;
+
+        let mut students = HashMap::new();
+        for row in reader.deserialize() {
+            let enrollment: Enrollment =
+                row.map_err(|e| format!("Invalid roster {}: {}", path.display(), e))?;
+            students.insert(enrollment.csid.to_ascii_lowercase(), enrollment);
+        }
+        Ok(Roster { students })
+    }
+
+    pub fn get(&self, csid: &str) -> Option<&Enrollment> {
+        self.students.get(&csid.trim().to_ascii_lowercase())
+    }
+}
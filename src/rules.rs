@@ -0,0 +1,134 @@
+use crate::history::Visit;
+use crate::queue::Queue;
+use chrono::{DateTime, Duration, Local, Utc};
+
+/// Limits on how often one student can get help, keyed by CSID. Unset limits
+/// are not enforced.
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    pub max_visits_per_session: Option<usize>,
+    pub cooldown_minutes: Option<i64>,
+    pub max_help_minutes_per_day: Option<i64>,
+}
+
+/// Why a student may not join, and when they can try again.
+#[derive(Debug)]
+pub struct Rejection {
+    pub reason: String,
+    /// `None` when they have to wait for the next session.
+    pub retry_at: Option<DateTime<Local>>,
+}
+
+impl Rules {
+    pub fn check(&self, queue: &Queue, csid: &str, now: DateTime<Utc>) -> Result<(), Rejection> {
+        let helped: Vec<&Visit> = queue
+            .visits()
+            .iter()
+            .filter(|visit| visit.claimed_at.is_some())
+            .filter(|visit| visit.csid.trim().eq_ignore_ascii_case(csid.trim()))
+            .collect();
+
+        if let Some(max) = self.max_visits_per_session {
+            let visits = helped
+                .iter()
+                .filter(|visit| visit.joined_at >= queue.session_started)
+                .count();
+            if visits >= max {
+                return Err(Rejection {
+                    reason: format!("You have already been helped {} times this session.", visits),
+                    retry_at: None,
+                });
+            }
+        }
+
+        if let Some(minutes) = self.cooldown_minutes {
+            if let Some(last) = helped.iter().map(|visit| visit.finished_at).max() {
+                let retry_at = last + Duration::minutes(minutes);
+                if now < retry_at {
+                    return Err(Rejection {
+                        reason: format!(
+                            "Students must wait {} minutes after being helped before rejoining.",
+                            minutes
+                        ),
+                        retry_at: Some(retry_at.with_timezone(&Local)),
+                    });
+                }
+            }
+        }
+
+        if let Some(max) = self.max_help_minutes_per_day {
+            let today = now.with_timezone(&Local).date_naive();
+            let minutes: i64 = helped
+                .iter()
+                .filter(|visit| visit.finished_at.with_timezone(&Local).date_naive() == today)
+                .filter_map(|visit| Some((visit.finished_at - visit.claimed_at?).num_minutes()))
+                .sum();
+            if minutes >= max {
+                let tomorrow = (today + Duration::days(1))
+                    .and_hms_opt(0, 0, 0)
+                    .and_then(|midnight| midnight.and_local_timezone(Local).earliest());
+                return Err(Rejection {
+                    reason: format!(
+                        "You have already had {} minutes of help today, the daily limit is {}.",
+                        minutes, max
+                    ),
+                    retry_at: tomorrow,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::testing::{local, visit, with_visits};
+
+    fn check(rules: &Rules, queue: &Queue, now: DateTime<Local>) -> Result<(), Rejection> {
+        rules.check(queue, "111", now.with_timezone(&Utc))
+    }
+
+    #[test]
+    fn cooldown_runs_from_the_end_of_the_last_visit() {
+        let rules = Rules { cooldown_minutes: Some(30), ..Rules::default() };
+        let queue = with_visits(vec![
+            visit("111", local(14, 9, 0), local(14, 9, 10)),
+            visit("111", local(14, 11, 30), local(14, 11, 50)),
+        ]);
+        let rejection = check(&rules, &queue, local(14, 12, 19)).unwrap_err();
+        assert_eq!(rejection.retry_at, Some(local(14, 12, 20)));
+        assert!(check(&rules, &queue, local(14, 12, 20)).is_ok());
+    }
+
+    #[test]
+    fn daily_cap_adds_up_help_time_today() {
+        let rules = Rules { max_help_minutes_per_day: Some(60), ..Rules::default() };
+        let mut visits = vec![
+            // Yesterday does not count
+            visit("111", local(13, 10, 0), local(13, 11, 0)),
+            visit("111", local(14, 10, 0), local(14, 10, 40)),
+            visit("111", local(14, 11, 0), local(14, 11, 19)),
+            // Other students do not count
+            visit("222", local(14, 11, 0), local(14, 11, 30)),
+        ];
+        assert!(check(&rules, &with_visits(visits.clone()), local(14, 12, 0)).is_ok());
+
+        visits.push(visit("111", local(14, 11, 30), local(14, 11, 31)));
+        let rejection = check(&rules, &with_visits(visits), local(14, 12, 0)).unwrap_err();
+        assert_eq!(rejection.retry_at, Some(local(15, 0, 0)));
+    }
+
+    #[test]
+    fn session_cap_ignores_earlier_sessions() {
+        let rules = Rules { max_visits_per_session: Some(1), ..Rules::default() };
+        let mut queue = with_visits(vec![visit("111", local(14, 9, 0), local(14, 9, 10))]);
+        queue.session_started = local(14, 11, 0).with_timezone(&Utc);
+        assert!(check(&rules, &queue, local(14, 12, 0)).is_ok());
+
+        queue.session_started = local(14, 8, 0).with_timezone(&Utc);
+        let rejection = check(&rules, &queue, local(14, 12, 0)).unwrap_err();
+        assert_eq!(rejection.retry_at, None);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::queue::{Queue, QueueState};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveTime, Weekday};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// How often the schedule is checked against the clock.
+const CHECK_EVERY: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// One TA's weekly office hours, e.g.
+///
+/// ```toml
+/// [[window]]
+/// ta = "Alice"
+/// day = "Mon"
+/// start = "14:00"
+/// end = "16:00"
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Window {
+    pub ta: String,
+    pub day: Weekday,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+/// Weekly office-hour windows that open and close the queue automatically.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Schedule {
+    /// Minutes before a window ends during which no new students may join.
+    #[serde(default)]
+    pub soft_close_minutes: i64,
+    #[serde(rename = "window", default)]
+    pub windows: Vec<Window>,
+}
+
+/// A concrete occurrence of a window, as shown on the closed page.
+#[derive(Serialize, Debug)]
+pub struct Hours {
+    pub ta: String,
+    pub date: NaiveDate,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl Schedule {
+    pub fn load(path: &Path) -> Result<Schedule, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read schedule {}: {}", path.display(), e))?;
+        let schedule: Schedule = toml::from_str(&data)
+            .map_err(|e| format!("Invalid schedule {}: {}", path.display(), e))?;
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.soft_close_minutes < 0 {
+            return Err("soft_close_minutes cannot be negative".to_string());
+        }
+        for window in &self.windows {
+            if window.start >= window.end {
+                return Err(format!(
+                    "{}'s window on {} must start before it ends",
+                    window.ta, window.day
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The state the queue should be in at `now`.
+    pub fn state_at(&self, now: DateTime<Local>) -> QueueState {
+        let time = now.time();
+        let soft_close = Duration::minutes(self.soft_close_minutes);
+        let mut state = QueueState::Closed;
+        for window in self.windows.iter().filter(|w| w.day == now.weekday()) {
+            if window.start <= time && time < window.end - soft_close {
+                return QueueState::Open;
+            }
+            if window.start <= time && time < window.end {
+                state = QueueState::SoftClosed;
+            }
+        }
+        state
+    }
+
+    /// Windows that have not ended yet over the coming week, soonest first.
+    pub fn upcoming(&self, now: DateTime<Local>) -> Vec<Hours> {
+        let today = now.date_naive();
+        let mut hours: Vec<Hours> = (0..7)
+            .map(|offset| today + Duration::days(offset))
+            .flat_map(|date| {
+                self.windows
+                    .iter()
+                    .filter(move |w| w.day == date.weekday())
+                    .filter(move |w| date > today || w.end > now.time())
+                    .map(move |w| Hours {
+                        ta: w.ta.clone(),
+                        date,
+                        start: w.start,
+                        end: w.end,
+                    })
+            })
+            .collect();
+        hours.sort_by_key(|h| (h.date, h.start));
+        hours
+    }
+}
+
+/// Moves the queue between states as windows start and end, starting a new
+/// session whenever a window opens. Only transitions in the schedule change
+/// the queue, so staff can still open or close it by hand in between.
+pub async fn run(schedule: Schedule, queue_ref: Arc<Mutex<Queue>>) {
+    let mut interval = tokio::time::interval(CHECK_EVERY);
+    let mut last = None;
+    loop {
+        interval.tick().await;
+        let state = schedule.state_at(Local::now());
+        if last != Some(state) {
+            info!("Schedule sets the queue to {:?}", state);
+            let mut queue = queue_ref.lock().unwrap();
+            let mut result = queue.set_state(state);
+            // A window opening starts a new session, so visit limits reset
+            // without anyone at the TUI. Restarting mid-window does not.
+            if state == QueueState::Open && last.is_some() {
+                result = result.and_then(|_| queue.start_session());
+            }
+            if let Err(e) = result {
+                warn!("Schedule could not change the queue: {}", e);
+            }
+            last = Some(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::testing::local;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    /// Alice on Wednesdays from 14:00 to 16:00 and Bob on Mondays from 10:00
+    /// to 12:00, closing to new students 15 minutes early.
+    fn schedule() -> Schedule {
+        let window = |ta: &str, day, start, end| Window { ta: ta.to_string(), day, start, end };
+        Schedule {
+            soft_close_minutes: 15,
+            windows: vec![
+                window("Alice", Weekday::Wed, time(14, 0), time(16, 0)),
+                window("Bob", Weekday::Mon, time(10, 0), time(12, 0)),
+            ],
+        }
+    }
+
+    #[test]
+    fn state_soft_closes_before_the_window_ends() {
+        let schedule = schedule();
+        assert_eq!(schedule.state_at(local(14, 13, 59)), QueueState::Closed);
+        assert_eq!(schedule.state_at(local(14, 14, 0)), QueueState::Open);
+        assert_eq!(schedule.state_at(local(14, 15, 44)), QueueState::Open);
+        assert_eq!(schedule.state_at(local(14, 15, 45)), QueueState::SoftClosed);
+        assert_eq!(schedule.state_at(local(14, 15, 59)), QueueState::SoftClosed);
+        assert_eq!(schedule.state_at(local(14, 16, 0)), QueueState::Closed);
+        // Same hours on another day
+        assert_eq!(schedule.state_at(local(15, 14, 30)), QueueState::Closed);
+    }
+
+    #[test]
+    fn upcoming_drops_windows_that_have_ended() {
+        let schedule = schedule();
+        let dates = |now| -> Vec<(String, NaiveDate)> {
+            schedule.upcoming(now).into_iter().map(|h| (h.ta, h.date)).collect()
+        };
+        let wednesday = NaiveDate::from_ymd_opt(2026, 10, 14).unwrap();
+        let monday = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        assert_eq!(
+            dates(local(14, 15, 50)),
+            [("Alice".to_string(), wednesday), ("Bob".to_string(), monday)]
+        );
+        assert_eq!(dates(local(14, 16, 0)), [("Bob".to_string(), monday)]);
+    }
+}
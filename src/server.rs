@@ -1,22 +1,173 @@
-use actix_files as fs;
-
+use crate::admin;
+use crate::api::{self, ApiError, AttachRequest};
+use crate::assets;
+use crate::checkoff::Rubric;
+use crate::config::Listen;
 use crate::queue::*;
-use actix_web::{web, App, HttpResponse, HttpServer};
+use crate::rooms::Rooms;
+use crate::roster::Roster;
+use crate::rules::{Rejection, Rules};
+use crate::schedule::Schedule;
+use crate::stats::{self, Stats};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::Payload;
+use actix_web::{error, web, web::Bytes, App, FromRequest, HttpRequest, HttpResponse, HttpServer, ResponseError};
+use futures_util::stream;
 use std::fs::OpenOptions;
 use std::io::Write;
-use log::info;
+use log::{info, warn};
+use serde::Serialize;
+use std::future::{ready, Ready};
+use std::ops::Deref;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
 
 pub enum ServerControlMessage {
     Start,
     Stop,
 }
-const ADDRESS: &str = "0.0.0.0";
+
+/// Startup configuration shared with every request handler.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Token required by the admin API; the API is disabled when unset.
+    pub staff_token: Option<String>,
+    pub duplicate_policy: DuplicatePolicy,
+    pub schedule: Option<Schedule>,
+    pub rules: Rules,
+    /// Students allowed to join; anyone may join when unset.
+    pub roster: Option<Roster>,
+    /// Whether CSIDs missing from the roster may still join.
+    pub allow_guests: bool,
+    /// Labs students can get checked off; any lab name is accepted when unset.
+    pub rubric: Option<Rubric>,
+    /// Lab rooms students can give as their location; any room is accepted
+    /// when unset.
+    pub rooms: Option<Rooms>,
+    /// Directory of files that replace or add to the built-in pages and
+    /// stylesheet.
+    pub static_dir: Option<PathBuf>,
+}
+
+/// What to do when a CSID that is already in line joins again.
+#[derive(Clone, Copy, Debug)]
+pub enum DuplicatePolicy {
+    /// Refuse the second request.
+    Reject,
+    /// Send the student back to the waiting page of their existing request.
+    Redirect,
+}
+
+impl std::str::FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reject" => Ok(DuplicatePolicy::Reject),
+            "redirect" => Ok(DuplicatePolicy::Redirect),
+            _ => Err(format!("unknown policy '{}', expected reject or redirect", s)),
+        }
+    }
+}
+/// The queue a request is for. Routes under `/q/{name}` pick a queue by name
+/// and everything else uses the default queue.
+pub struct QueueRef {
+    queue: Arc<Mutex<Queue>>,
+    /// URL prefix of the queue's pages, empty for the default queue.
+    pub base: String,
+}
+
+impl Deref for QueueRef {
+    type Target = Arc<Mutex<Queue>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.queue
+    }
+}
+
+impl FromRequest for QueueRef {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<QueueRef, actix_web::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let queues = req
+            .app_data::<web::Data<Queues>>()
+            .expect("Queues are registered with the app");
+        let queue_ref = match req.match_info().get("name") {
+            None => Ok(QueueRef {
+                queue: queues.first().clone(),
+                base: String::new(),
+            }),
+            Some(name) => queues
+                .get(name)
+                .map(|queue| QueueRef {
+                    queue: queue.clone(),
+                    base: format!("/q/{}", name),
+                })
+                .ok_or_else(|| error::ErrorNotFound(format!("There is no queue named {}.", name))),
+        };
+        ready(queue_ref)
+    }
+}
+
+/// Routes for one queue. These are mounted at the root for the default queue
+/// and under `/q/{name}` for every queue, so pages use relative links.
+fn queue_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(index))
+        .route("/waiting", web::get().to(|settings: web::Data<Settings>| async move {
+            page(&settings, "waiting.html")
+        }))
+        .route("/done", web::get().to(|settings: web::Data<Settings>| async move {
+            page(&settings, "done.html")
+        }))
+        .route("/attach", web::get().to(|settings: web::Data<Settings>| async move {
+            page(&settings, "attach.html")
+        }))
+        .route("/api/join", web::post().to(join_queue))
+        .route("/api/attach", web::post().to(attach_to_request))
+        .route("/api/invite", web::get().to(get_invite))
+        .route("/api/leave", web::post().to(leave_queue))
+        .route("/api/position", web::get().to(get_position))
+        .route("/api/events", web::get().to(stream_updates))
+        .route("/api/stats", web::get().to(get_stats))
+        .route("/api/hours", web::get().to(get_hours))
+        .route("/api/labs", web::get().to(get_labs))
+        .route("/api/rooms", web::get().to(get_rooms))
+        .configure(api::configure)
+        .configure(admin::configure);
+}
+
+/// Serves an HTML page, preferring a themed copy in the static directory.
+fn page(settings: &Settings, name: &str) -> HttpResponse {
+    match assets::get(settings.static_dir.as_deref(), name) {
+        Some(data) => HttpResponse::Ok().content_type(assets::content_type(name)).body(data),
+        None => HttpResponse::NotFound().content_type("text/html").body("Error loading page"),
+    }
+}
+
+/// Serves the stylesheet and any other file from the static directory.
+async fn static_asset(settings: web::Data<Settings>, name: web::Path<String>) -> HttpResponse {
+    match assets::get(settings.static_dir.as_deref(), &name) {
+        Some(data) => HttpResponse::Ok().content_type(assets::content_type(&name)).body(data),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Relative links on a queue's pages only resolve under `/q/{name}/`.
+async fn add_trailing_slash(data: QueueRef) -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .append_header(("Location", format!("{}/", data.base)))
+        .finish()
+}
+
 use std::io;
 /// This function starts the server and defines the routes for the web application.
 use tokio::sync::mpsc;
 pub async fn http_server(
-    queue_ref: Arc<Mutex<Queue>>,
+    queues: Queues,
+    settings: Settings,
+    listen: Listen,
     mut rx: mpsc::Receiver<ServerControlMessage>,
 ) -> io::Result<()> {
     let mut server_handle: Option<actix_web::dev::ServerHandle> = None;
@@ -26,71 +177,40 @@ pub async fn http_server(
         match msg {
             ServerControlMessage::Start => {
                 if server_handle.is_none() {
-                    let mut port = 3000;
-                    let max_port = 3050;
-
-                    while port <= max_port {
-                        let q = web::Data::new(queue_ref.clone());
+                    for port in listen.ports.first..=listen.ports.last {
+                        let q = web::Data::new(queues.clone());
+                        let settings = web::Data::new(settings.clone());
                         let server = HttpServer::new(move || {
                             App::new()
                                 .app_data(q.clone())
-                                .route(
-                                    "/",
-                                    web::get().to(|| async {
-                                        HttpResponse::Ok().content_type("text/html").body(
-                                            std::fs::read_to_string("src/public/index.html")
-                                                .unwrap_or_else(|_| {
-                                                    "Error loading page".to_string()
-                                                }),
-                                        )
-                                    }),
-                                )
-                                .route(
-                                    "/waiting",
-                                    web::get().to(|| async {
-                                        HttpResponse::Ok().content_type("text/html").body(
-                                            std::fs::read_to_string("src/public/waiting.html")
-                                                .unwrap_or_else(|_| {
-                                                    "Error loading page".to_string()
-                                                }),
-                                        )
-                                    }),
-                                )
-                                .route(
-                                    "/done",
-                                    web::get().to(|| async {
-                                        HttpResponse::Ok().content_type("text/html").body(
-                                            std::fs::read_to_string("src/public/done.html")
-                                                .unwrap_or_else(|_| {
-                                                    "Error loading page".to_string()
-                                                }),
-                                        )
-                                    }),
-                                )
+                                .app_data(settings.clone())
+                                .route("/static/{name}", web::get().to(static_asset))
                                 .service(
-                                    fs::Files::new("/static", "src/public").show_files_listing(),
+                                    web::scope("/q/{name}")
+                                        .route("", web::get().to(add_trailing_slash))
+                                        .configure(queue_routes),
                                 )
-                                .route("/api/join", web::post().to(join_queue))
-                                .route("/api/leave", web::post().to(leave_queue))
-                                .route("/api/position", web::get().to(get_position))
+                                .configure(queue_routes)
                         });
 
-                        match server
+                        // Try the next port if binding fails
+                        if let Ok(server) = server
                             .shutdown_timeout(1)
-                            .bind(format!("{}:{}", ADDRESS, port))
+                            .bind((listen.address.as_str(), port))
                             .map(|s| s.run())
                         {
-                            Ok(server) => {
-                                server_handle = Some(actix_web::dev::Server::handle(&server));
-                                log_server_details(port).expect("Failed to log server details");
-                                info!("Serving on {}:{}", ADDRESS, port);
-
-                                tokio::spawn(async move {
-                                    server.await.expect("Server failed");
-                                });
-                                break;
+                            server_handle = Some(actix_web::dev::Server::handle(&server));
+                            if let Some(path) = &listen.hint_file {
+                                if let Err(e) = log_server_details(path, port) {
+                                    warn!("Could not write connection details to {}: {}", path.display(), e);
+                                }
                             }
-                            Err(_) => port += 1, // Increment the port if binding fails
+                            info!("Serving on {}:{}", listen.address, port);
+
+                            tokio::spawn(async move {
+                                server.await.expect("Server failed");
+                            });
+                            break;
                         }
                     }
 
@@ -123,43 +243,40 @@ use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::process::Command;
 
-fn log_server_details(port: u16) -> std::io::Result<()> {
+/// Writes SSH instructions for reaching the server on `port` to `file_path`.
+fn log_server_details(file_path: &Path, port: u16) -> std::io::Result<()> {
     // Get the hostname
     let output = Command::new("hostname").output()?;
     let hostname = String::from_utf8_lossy(&output.stdout);
 
-    // Get the home directory
-    let home_dir = std::env::var("HOME").expect("HOME environment variable not set");
-    let dir_path = Path::new(&home_dir).join("Public");
-    let file_path = dir_path.join("join-office-hours.txt");
-
     // Create the directory if it does not exist
-    if !dir_path.exists() {
-        create_dir_all(&dir_path)?;
+    let missing_dir = file_path.parent().filter(|x| !x.as_os_str().is_empty() && !x.exists());
+    if let Some(dir_path) = missing_dir {
+        create_dir_all(dir_path)?;
         // Set directory permissions if needed
-        let mut dir_permissions = metadata(&dir_path)?.permissions();
+        let mut dir_permissions = metadata(dir_path)?.permissions();
         dir_permissions.set_mode(0o755); // rwxr-xr-x
-        set_permissions(&dir_path, dir_permissions)?;
+        set_permissions(dir_path, dir_permissions)?;
     }
 
     // Open the file with append and create options
     let mut file = OpenOptions::new()
         .append(true)
         .create(true)
-        .open(&file_path)?;
+        .open(file_path)?;
 
     // Set file permissions if needed
     let mut file_permissions = file.metadata()?.permissions();
     file_permissions.set_mode(0o644); // rw-r--r--
-    set_permissions(&file_path, file_permissions)?;
+    set_permissions(file_path, file_permissions)?;
 
     // Log entry
     let log_entry = format!(
         "Connect via:\n\
-        \tssh -N -L {}:{}:3000 <your-username>@<your-machine>\n\
-        Visit http://localhost:3000 to join the office hours queue.\n",
-        port,
-        hostname.trim()
+        \tssh -N -L {port}:{}:{port} <your-username>@<your-machine>\n\
+        Visit http://localhost:{port} to join the office hours queue.\n",
+        hostname.trim(),
+        port = port,
     );
 
     file.set_len(0)?;
@@ -168,89 +285,529 @@ fn log_server_details(port: u16) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Serves the join form while the queue is open, and a page listing the next
+/// office hours otherwise.
+async fn index(data: QueueRef, settings: web::Data<Settings>) -> HttpResponse {
+    if data.lock().unwrap().state.accepts_joins() {
+        page(&settings, "index.html")
+    } else {
+        page(&settings, "closed.html")
+    }
+}
+
+pub async fn get_hours(settings: web::Data<Settings>) -> HttpResponse {
+    let hours = match &settings.schedule {
+        Some(schedule) => schedule.upcoming(chrono::Local::now()),
+        None => vec![],
+    };
+    HttpResponse::Ok().json(hours)
+}
+
+/// Names of the labs that can be checked off.
+pub async fn get_labs(settings: web::Data<Settings>) -> HttpResponse {
+    let labs: Vec<&str> = match &settings.rubric {
+        Some(rubric) => rubric.labs.iter().map(|lab| lab.name.as_str()).collect(),
+        None => vec![],
+    };
+    HttpResponse::Ok().json(labs)
+}
+
+/// Room layouts, for picking a room and seat when joining.
+pub async fn get_rooms(settings: web::Data<Settings>) -> HttpResponse {
+    match &settings.rooms {
+        Some(rooms) => HttpResponse::Ok().json(&rooms.rooms),
+        None => HttpResponse::Ok().json(Vec::<()>::new()),
+    }
+}
+
 async fn join_queue(
-    data: web::Data<Arc<Mutex<Queue>>>,
+    data: QueueRef,
+    settings: web::Data<Settings>,
     form: web::Form<StudentInfo>,
 ) -> HttpResponse {
-    if form.name.is_empty()
-        || form.csid.is_empty()
-        || form.purpose.is_empty()
-        || form.details.is_empty()
-        || form.steps.is_empty()
-    {
-        return HttpResponse::BadRequest()
-            .content_type("text/html")
-            .body("Please complete the requirements to join the office hour queue.");
+    let mut queue = data.lock().unwrap();
+    let student_request = match admit(&queue, &settings, &form) {
+        Ok(request) => request,
+        Err(e) => return html_error(&settings, e),
+    };
+
+    info!("Student request received: {:?}", student_request);
+
+    match queue.add(student_request.clone()) {
+        Ok(_) => {
+            // send to /waiting?id
+            HttpResponse::Found()
+                .append_header(("Location", format!("{}/waiting?id={}", data.base, student_request.id)))
+                .cookie(secret_cookie(&student_request.id, &student_request.secret))
+                .finish()
+        }
+        Err(e) => join_error(&data, &settings, e),
     }
+}
 
-    let student_info = StudentInfo::new(
-        form.name.clone(),
-        form.csid.clone(),
+/// Checks a join form against the settings and the visit rules, and builds
+/// the request to add.
+pub fn admit(queue: &Queue, settings: &Settings, form: &StudentInfo) -> Result<StudentRequest, ApiError> {
+    // Checkoffs only need the lab; help requests need the details
+    let complete = match form.kind {
+        RequestKind::Help => !form.details.is_empty() && !form.steps.is_empty(),
+        RequestKind::Checkoff => !form.lab.is_empty(),
+    };
+    if form.name.is_empty() || form.csid.is_empty() || form.purpose.is_empty() || !complete {
+        return Err(ApiError::Invalid(
+            "Please complete the requirements to join the office hour queue.".to_string(),
+        ));
+    }
+    if form.kind == RequestKind::Checkoff {
+        if let Some(rubric) = &settings.rubric {
+            if rubric.lab(&form.lab).is_none() {
+                return Err(ApiError::Invalid(format!("There is no checkoff for {}.", form.lab)));
+            }
+        }
+    }
+
+    if !form.seat.trim().is_empty() && form.room.trim().is_empty() {
+        return Err(ApiError::Invalid("Please say which room your seat is in.".to_string()));
+    }
+    if let (Some(rooms), false) = (&settings.rooms, form.room.trim().is_empty()) {
+        let valid = rooms
+            .get(&form.room)
+            .is_some_and(|room| form.seat.trim().is_empty() || room.seat(&form.seat).is_some());
+        if !valid {
+            return Err(ApiError::Invalid(format!("There is no seat {} in {}.", form.seat, form.room)));
+        }
+    }
+
+    let (name, csid, section) = enroll(settings, &form.name, &form.csid)?;
+    if let Err(rejection) = settings.rules.check(queue, &csid, chrono::Utc::now()) {
+        info!("Rejected {}: {}", csid, rejection.reason);
+        return Err(ApiError::Rejected(rejection));
+    }
+
+    let mut student_info = StudentInfo::new(
+        name,
+        csid,
         form.purpose.clone(),
         form.details.clone(),
         form.steps.clone(),
     );
+    student_info.section = section;
+    student_info.kind = form.kind;
+    student_info.lab = form.lab.clone();
+    // Prefer the layout's spelling of the room
+    student_info.room = match settings.rooms.as_ref().and_then(|rooms| rooms.get(&form.room)) {
+        Some(room) => room.name.clone(),
+        None => form.room.trim().to_string(),
+    };
+    student_info.seat = form.seat.trim().to_ascii_uppercase();
+    Ok(StudentRequest::new(student_info))
+}
 
-    let student_request = StudentRequest::new(student_info);
-
-    info!("Student request received: {:?}", student_request);
+/// Checks a partner's attach form like `admit` does for joins.
+pub fn admit_member(queue: &Queue, settings: &Settings, form: &AttachRequest) -> Result<Member, ApiError> {
+    if form.invite.is_empty() || form.name.is_empty() || form.csid.is_empty() {
+        return Err(ApiError::Invalid(
+            "Please enter your name and CS ID to join your partner.".to_string(),
+        ));
+    }
+    let (name, csid, _) = enroll(settings, &form.name, &form.csid)?;
+    if let Err(rejection) = settings.rules.check(queue, &csid, chrono::Utc::now()) {
+        info!("Rejected {}: {}", csid, rejection.reason);
+        return Err(ApiError::Rejected(rejection));
+    }
+    Ok(Member::new(name, csid))
+}
 
-    match handle_join(data, student_request.clone()) {
-        Ok(_) => {
-            // send to /waiting?id
-            HttpResponse::Found()
-                .append_header(("Location", format!("/waiting?id={}", student_request.id)))
-                .finish()
+/// The roster's spelling of a student's name and CSID, and their section.
+/// Students missing from the roster are turned away unless guests are allowed.
+fn enroll(
+    settings: &Settings,
+    name: &str,
+    csid: &str,
+) -> Result<(String, String, Option<String>), ApiError> {
+    match settings.roster.as_ref().map(|roster| roster.get(csid)) {
+        Some(Some(enrollment)) => Ok((
+            enrollment.name.clone(),
+            enrollment.csid.clone(),
+            Some(enrollment.section.clone()),
+        )),
+        Some(None) if !settings.allow_guests => {
+            info!("Rejected unknown CSID {}", csid);
+            Err(ApiError::NotEnrolled)
         }
-        Err(_) => HttpResponse::InternalServerError()
+        _ => Ok((name.to_string(), csid.to_string(), None)),
+    }
+}
+
+/// The page shown when a join or attach form is turned away. Messages can
+/// quote the form, so they are escaped.
+fn html_error(settings: &Settings, error: ApiError) -> HttpResponse {
+    match error {
+        ApiError::Rejected(rejection) => rejected_page(settings, rejection),
+        e => HttpResponse::build(e.status_code())
             .content_type("text/html")
-            .body("An error occurred while processing your request."),
+            .body(escape_html(&e.to_string())),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn join_error(data: &QueueRef, settings: &Settings, error: JoinError) -> HttpResponse {
+    match (error, settings.duplicate_policy) {
+        (JoinError::Duplicate { id }, DuplicatePolicy::Redirect) => HttpResponse::Found()
+            .append_header(("Location", format!("{}/waiting?id={}", data.base, id)))
+            .finish(),
+        (e, _) => html_error(settings, e.into()),
     }
 }
 
-/// This function handles the student request by appending to the queue.
-fn handle_join(data: web::Data<Arc<Mutex<Queue>>>, request: StudentRequest) -> Result<(), ()> {
+/// Adds a partner to an existing request using its invite code.
+async fn attach_to_request(
+    data: QueueRef,
+    settings: web::Data<Settings>,
+    form: web::Form<AttachRequest>,
+) -> HttpResponse {
     let mut queue = data.lock().unwrap();
-    queue.add(request);
-    Ok(())
+    let member = match admit_member(&queue, &settings, &form) {
+        Ok(member) => member,
+        Err(e) => return html_error(&settings, e),
+    };
+    let secret = member.secret.clone();
+    match queue.attach(&form.invite, member) {
+        Ok(id) => HttpResponse::Found()
+            .append_header(("Location", format!("{}/waiting?id={}", data.base, id)))
+            .cookie(secret_cookie(&id, &secret))
+            .finish(),
+        Err(e) => join_error(&data, &settings, e),
+    }
+}
+
+/// The invite code partners use to attach to a request.
+async fn get_invite(
+    req: HttpRequest,
+    data: QueueRef,
+    query: web::Query<IdQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let queue = data.lock().unwrap();
+    let secret = request_secret(&req, &query.id);
+    queue.authorize(&query.id, secret.as_deref())?;
+    let request = queue.find(&query.id).expect("Authorized requests are in the queue");
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "invite": request.invite,
+        "names": request.names(),
+    })))
+}
+
+/// Explains why a student was turned away and when they may rejoin.
+fn rejected_page(settings: &Settings, rejection: Rejection) -> HttpResponse {
+    let retry = match rejection.retry_at {
+        Some(at) => format!("You can rejoin after {}.", at.format("%A %-I:%M %p")),
+        None => "You can rejoin next session.".to_string(),
+    };
+    let page = match assets::get(settings.static_dir.as_deref(), "rejected.html") {
+        Some(page) => String::from_utf8_lossy(&page)
+            .replace("{reason}", &rejection.reason)
+            .replace("{retry}", &retry),
+        None => format!("{} {}", rejection.reason, retry),
+    };
+    HttpResponse::TooManyRequests()
+        .content_type("text/html")
+        .body(page)
+}
+
+/// The public id appears in URLs, so a separate secret, stored in a cookie
+/// scoped to that id, proves the browser is the one that joined. Partners
+/// get their own secret for the same id.
+pub fn secret_cookie(id: &str, secret: &str) -> Cookie<'static> {
+    Cookie::build(format!("oh_queue_{}", id), secret.to_string())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish()
+}
+
+pub fn request_secret(req: &HttpRequest, id: &str) -> Option<String> {
+    req.cookie(&format!("oh_queue_{}", id))
+        .map(|cookie| cookie.value().to_string())
 }
 
 async fn leave_queue(
-    data: web::Data<Arc<Mutex<Queue>>>,
+    req: HttpRequest,
+    data: QueueRef,
+    settings: web::Data<Settings>,
     query: web::Query<IdQuery>,
 ) -> HttpResponse {
-    match handle_leave(data, query.id.clone()) {
+    let secret = request_secret(&req, &query.id);
+    match handle_leave(&data, query.id.clone(), secret) {
         Ok(_) => HttpResponse::Found()
-            .append_header(("Location", "/done"))
+            .append_header(("Location", format!("{}/done", data.base)))
             .finish(),
-        Err(_) => HttpResponse::InternalServerError()
-            .content_type("text/html")
-            .body("An error occurred while processing your request."),
+        Err(e) => html_error(&settings, e.into()),
     }
 }
 
-fn handle_leave(data: web::Data<Arc<Mutex<Queue>>>, id: String) -> Result<(), ()> {
+fn handle_leave(
+    data: &Mutex<Queue>,
+    id: String,
+    secret: Option<String>,
+) -> Result<(), QueueError> {
     let mut queue = data.lock().unwrap();
-    queue.remove(id)
+    queue.leave(id, secret.as_deref())
 }
 
 async fn get_position(
-    data: web::Data<Arc<Mutex<Queue>>>,
+    req: HttpRequest,
+    data: QueueRef,
     query: web::Query<IdQuery>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     info!("Position requested for ID: {}", query.id);
-    match handle_position(data, query.id.clone()) {
-        Ok(position) => HttpResponse::Ok().content_type("text/html").body(position),
-        Err(_) => HttpResponse::InternalServerError()
-            .content_type("text/html")
-            .body("An error occurred while processing your request."),
+    let secret = request_secret(&req, &query.id);
+    if still_waiting_after_close(&data.lock().unwrap(), &query.id) {
+        return Err(ApiError::Closed);
     }
+    let position = handle_position(&data, query.id.clone(), secret)?;
+    Ok(HttpResponse::Ok().json(position))
+}
+
+/// A student's place in line and roughly how long until a TA gets to them.
+#[derive(Serialize, PartialEq)]
+struct Position {
+    position: usize,
+    estimated_wait_minutes: i64,
+}
+
+impl Position {
+    fn of(queue: &Queue, position: usize) -> Position {
+        Position {
+            position,
+            estimated_wait_minutes: stats::estimated_wait(queue, position).num_minutes(),
+        }
+    }
+}
+
+/// Whether the queue closed while `id` was still waiting, which archives the
+/// request. Students already being helped keep seeing their request.
+pub fn still_waiting_after_close(queue: &Queue, id: &str) -> bool {
+    queue.state == QueueState::Closed && queue.find(id).is_none_or(|x| x.is_waiting())
+}
+
+fn handle_position(
+    data: &Mutex<Queue>,
+    id: String,
+    secret: Option<String>,
+) -> Result<Position, QueueError> {
+    let queue = data.lock().unwrap();
+    queue.authorize(&id, secret.as_deref())?;
+    let position = queue.position(id)?;
+    Ok(Position::of(&queue, position))
 }
 
-fn handle_position(data: web::Data<Arc<Mutex<Queue>>>, id: String) -> Result<String, ()> {
+
+pub async fn get_stats(data: QueueRef) -> HttpResponse {
     let queue = data.lock().unwrap();
-    match queue.position(id) {
-        Ok(position) => Ok(position.to_string()),
-        Err(_) => Err(()),
+    HttpResponse::Ok().json(Stats::compute(&queue, chrono::Utc::now()))
+}
+
+/// What a waiting student should be told about their request.
+#[derive(PartialEq)]
+enum Update {
+    Position(Position),
+    Up {
+        ta: String,
+        location: Option<String>,
+        meeting_link: Option<String>,
+    },
+    Removed,
+    /// Office hours ended while the student was still waiting.
+    Closed,
+}
+
+impl Update {
+    fn current(queue: &Queue, id: &str) -> Update {
+        match queue.find(id) {
+            // Closing the queue archives everyone still waiting
+            None if queue.state == QueueState::Closed => Update::Closed,
+            None => Update::Removed,
+            Some(request) if request.is_waiting() && queue.state == QueueState::Closed => Update::Closed,
+            Some(request) => match request.helper() {
+                Some(ta) => Update::Up {
+                    ta: ta.to_string(),
+                    location: request.location.clone(),
+                    meeting_link: request.meeting_link.clone(),
+                },
+                None => {
+                    let position = queue.position(id.to_string()).unwrap_or(0);
+                    Update::Position(Position::of(queue, position))
+                }
+            },
+        }
+    }
+
+    /// Formats the update as a Server-Sent Event.
+    fn to_event(&self) -> Bytes {
+        let (name, data) = match self {
+            Update::Position(position) => ("position", serde_json::to_string(position).unwrap()),
+            Update::Up { ta, location, meeting_link } => {
+                let data = serde_json::json!({
+                    "ta": ta,
+                    "location": location,
+                    "meeting_link": meeting_link,
+                });
+                ("up", data.to_string())
+            }
+            Update::Removed => ("removed", String::new()),
+            Update::Closed => ("closed", String::new()),
+        };
+        Bytes::from(format!("event: {}\ndata: {}\n\n", name, data))
+    }
+}
+
+/// Streams position changes for one request as Server-Sent Events. The
+/// current state is sent immediately, then again whenever the queue changes
+/// it. The stream ends once the request leaves the queue.
+async fn stream_updates(
+    req: HttpRequest,
+    data: QueueRef,
+    query: web::Query<IdQuery>,
+) -> HttpResponse {
+    updates(&req, &data, query.id.clone())
+}
+
+pub fn updates(req: &HttpRequest, data: &QueueRef, id: String) -> HttpResponse {
+    let queue_ref = Arc::clone(data);
+    let rx = {
+        let queue = queue_ref.lock().unwrap();
+        let authorized = queue.authorize(&id, request_secret(req, &id).as_deref()).is_ok();
+        if !authorized && !still_waiting_after_close(&queue, &id) {
+            return HttpResponse::NotFound().finish();
+        }
+        queue.subscribe()
+    };
+
+    let updates = stream::unfold(Some((rx, None)), move |state| {
+        let queue_ref = queue_ref.clone();
+        let id = id.clone();
+        async move {
+            let (mut rx, mut last) = state?;
+            loop {
+                let update = Update::current(&queue_ref.lock().unwrap(), &id);
+                if last.as_ref() != Some(&update) {
+                    let event = Ok::<_, actix_web::Error>(update.to_event());
+                    let next = match update {
+                        Update::Removed | Update::Closed => None,
+                        _ => Some((rx, Some(update))),
+                    };
+                    return Some((event, next));
+                }
+                last = Some(update);
+                match rx.recv().await {
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkoff::{Lab, Rubric};
+    use crate::queue::testing::student;
+    use crate::rooms::Room;
+
+    fn settings() -> Settings {
+        Settings {
+            staff_token: None,
+            duplicate_policy: DuplicatePolicy::Redirect,
+            schedule: None,
+            rules: Rules::default(),
+            roster: None,
+            allow_guests: false,
+            rubric: None,
+            rooms: None,
+            static_dir: None,
+        }
+    }
+
+    fn form(room: &str, seat: &str) -> StudentInfo {
+        let mut form = StudentInfo::new(
+            "Ann".to_string(),
+            "111".to_string(),
+            Purpose::Debugging,
+            "details".to_string(),
+            "steps".to_string(),
+        );
+        form.room = room.to_string();
+        form.seat = seat.to_string();
+        form
+    }
+
+    async fn error_page(settings: &Settings, form: &StudentInfo) -> String {
+        let queue = Queue::new(PathBuf::from("queue.json"));
+        let e = admit(&queue, settings, form).expect_err("Form is refused");
+        let body = actix_web::body::to_bytes(html_error(settings, e).into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn unknown_seat_is_escaped() {
+        let mut settings = settings();
+        settings.rooms = Some(Rooms {
+            rooms: vec![Room { name: "Soda 271".to_string(), rows: 2, columns: 2 }],
+        });
+        let page = error_page(&settings, &form("<script>alert(1)</script>", "A1")).await;
+        assert!(!page.contains("<script>"));
+        assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    }
+
+    #[actix_web::test]
+    async fn unknown_lab_is_escaped() {
+        let mut settings = settings();
+        settings.rubric = Some(Rubric {
+            labs: vec![Lab { name: "lab1".to_string(), items: vec![] }],
+        });
+        let mut form = form("", "");
+        form.kind = RequestKind::Checkoff;
+        form.lab = "<img src=x onerror=alert(1)>".to_string();
+        let page = error_page(&settings, &form).await;
+        assert!(!page.contains("<img"));
+        assert!(page.contains("&lt;img src=x onerror=alert(1)&gt;"));
+    }
+
+    #[test]
+    fn closing_only_turns_away_students_still_waiting() {
+        let mut queue = Queue::new(PathBuf::from("queue.json"));
+        let helped = student("111");
+        let waiting = student("222");
+        let (helped_id, waiting_id) = (helped.id.clone(), waiting.id.clone());
+        queue.add(helped).unwrap();
+        queue.add(waiting).unwrap();
+        queue.claim(helped_id.clone(), "alice").unwrap();
+        queue.set_state(QueueState::Closed).unwrap();
+
+        assert!(matches!(Update::current(&queue, &helped_id), Update::Up { .. }));
+        assert!(!still_waiting_after_close(&queue, &helped_id));
+        assert!(Update::current(&queue, &waiting_id) == Update::Closed);
+        assert!(still_waiting_after_close(&queue, &waiting_id));
+        assert_eq!(queue.students.len(), 1);
     }
 }
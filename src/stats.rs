@@ -0,0 +1,159 @@
+use crate::queue::{Queue, StudentRequest};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Distribution of a set of durations, in minutes.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Summary {
+    pub count: usize,
+    pub average: f64,
+    pub median: f64,
+    pub p90: f64,
+}
+
+impl Summary {
+    pub fn of(durations: impl Iterator<Item = Duration>) -> Summary {
+        let mut minutes: Vec<f64> = durations
+            .map(|d| d.num_seconds() as f64 / 60.0)
+            .collect();
+        if minutes.is_empty() {
+            return Summary::default();
+        }
+        minutes.sort_by(|a, b| a.total_cmp(b));
+
+        Summary {
+            count: minutes.len(),
+            average: minutes.iter().sum::<f64>() / minutes.len() as f64,
+            median: percentile(&minutes, 50.0),
+            p90: percentile(&minutes, 90.0),
+        }
+    }
+}
+
+/// Nearest-rank percentile of already sorted, non-empty `values`.
+fn percentile(values: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * values.len() as f64).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+/// Statistics for the current office-hours session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Stats {
+    pub session_started: DateTime<Utc>,
+    pub waiting: usize,
+    pub being_helped: usize,
+    pub resolved: usize,
+    /// Time from joining until a TA claimed the request.
+    pub wait: Summary,
+    /// Time from being claimed until resolved.
+    pub help: Summary,
+    /// Requests resolved per hour since the session started.
+    pub throughput_per_hour: f64,
+}
+
+impl Stats {
+    pub fn compute(queue: &Queue, now: DateTime<Utc>) -> Stats {
+        let claimed = queue
+            .students
+            .iter()
+            .chain(queue.resolved.iter())
+            .filter(|x| x.joined_at >= queue.session_started);
+
+        let hours = (now - queue.session_started).num_seconds() as f64 / 3600.0;
+        let throughput_per_hour = if hours > 0.0 {
+            queue.resolved.len() as f64 / hours
+        } else {
+            0.0
+        };
+
+        Stats {
+            session_started: queue.session_started,
+            waiting: queue.waiting().count(),
+            being_helped: queue.students.iter().filter(|x| !x.is_waiting()).count(),
+            resolved: queue.resolved.len(),
+            wait: Summary::of(claimed.filter_map(wait_time)),
+            help: Summary::of(queue.resolved.iter().filter_map(help_time)),
+            throughput_per_hour,
+        }
+    }
+}
+
+pub fn wait_time(request: &StudentRequest) -> Option<Duration> {
+    Some(request.claimed_at? - request.joined_at)
+}
+
+pub fn help_time(request: &StudentRequest) -> Option<Duration> {
+    Some(request.resolved_at? - request.claimed_at?)
+}
+
+/// Number of most recently resolved requests used to estimate help time.
+const RECENT_HELPS: usize = 10;
+/// Assumed help time before anyone has been helped this session.
+const DEFAULT_HELP_MINUTES: i64 = 10;
+
+/// Estimates how long the student at `position` will wait, assuming each TA
+/// on duty this run, or currently helping someone, takes as long as recent
+/// help sessions did. TAs who only appear in the saved roster are not counted.
+pub fn estimated_wait(queue: &Queue, position: usize) -> Duration {
+    let recent: Vec<Duration> = queue
+        .resolved
+        .iter()
+        .rev()
+        .filter_map(help_time)
+        .take(RECENT_HELPS)
+        .collect();
+    let per_student = if recent.is_empty() {
+        Duration::minutes(DEFAULT_HELP_MINUTES)
+    } else {
+        recent.iter().sum::<Duration>() / recent.len() as i32
+    };
+
+    let tas = queue.tas_on_duty().max(1) as i32;
+    per_student * position as i32 / tas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::testing::student;
+    use crate::queue::Ta;
+    use std::path::PathBuf;
+
+    #[test]
+    fn estimate_ignores_saved_tas_who_are_off_duty() {
+        let mut queue = Queue::new(PathBuf::from("queue.json"));
+        for name in ["alice", "bob", "carol", "dave"] {
+            queue.add_ta(Ta::new(name.to_string()));
+        }
+        assert_eq!(estimated_wait(&queue, 2), Duration::minutes(2 * DEFAULT_HELP_MINUTES));
+
+        queue.go_on_duty(Ta::new("alice".to_string()));
+        queue.go_on_duty(Ta::new("bob".to_string()));
+        assert_eq!(estimated_wait(&queue, 2), Duration::minutes(DEFAULT_HELP_MINUTES));
+    }
+
+    #[test]
+    fn estimate_counts_tas_helping_someone() {
+        let mut queue = Queue::new(PathBuf::from("queue.json"));
+        queue.go_on_duty(Ta::new("alice".to_string()));
+        let helped = student("111");
+        let id = helped.id.clone();
+        queue.add(helped).unwrap();
+        queue.claim(id, "carol").unwrap();
+        assert_eq!(queue.tas_on_duty(), 2);
+        assert_eq!(estimated_wait(&queue, 4), Duration::minutes(2 * DEFAULT_HELP_MINUTES));
+    }
+
+    #[test]
+    fn estimate_drops_tas_who_went_off_duty() {
+        let mut queue = Queue::new(PathBuf::from("queue.json"));
+        queue.go_on_duty(Ta::new("alice".to_string()));
+        queue.go_on_duty(Ta::new("bob".to_string()));
+        // Alice has a second TUI open
+        queue.go_on_duty(Ta::new("alice".to_string()));
+        queue.go_off_duty("alice");
+        queue.go_off_duty("bob");
+        assert_eq!(queue.tas_on_duty(), 1);
+        assert_eq!(estimated_wait(&queue, 2), Duration::minutes(2 * DEFAULT_HELP_MINUTES));
+    }
+}